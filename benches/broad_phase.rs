@@ -0,0 +1,92 @@
+/*
+What finding the obstacles the boy touches costs, checking every
+obstacle against going through a SpatialGrid first.
+
+    cargo bench --bench broad_phase
+
+Obstacles are laid out like segments: a stone, a three box platform and
+two coins each, one segment every 600 pixels. The boy runs along the
+floor and over the platforms, the way he does in the game. Both ways
+have to find exactly the same obstacles.
+*/
+use std::{hint::black_box, time::Instant};
+use walk_the_dog::engine::{Rect, SpatialGrid};
+
+const QUERIES: usize = 100_000;
+const SEGMENT_WIDTH: i16 = 600;
+
+// Each obstacle's boxes, the way Obstacle::bounding_boxes hands them out.
+fn segments(count: i16) -> Vec<Vec<Rect>> {
+    (0..count)
+        .flat_map(|segment| {
+            let x = segment * SEGMENT_WIDTH;
+            vec![
+                vec![Rect::new_from_x_y(x + 150, 546, 90, 54)],
+                vec![
+                    Rect::new_from_x_y(x + 370, 400, 60, 54),
+                    Rect::new_from_x_y(x + 430, 400, 264, 93),
+                    Rect::new_from_x_y(x + 694, 400, 60, 54),
+                ],
+                vec![Rect::new_from_x_y(x + 183, 450, 24, 24)],
+                vec![Rect::new_from_x_y(x + 550, 340, 24, 24)],
+            ]
+        })
+        .collect()
+}
+
+fn bounds(boxes: &[Rect]) -> Rect {
+    boxes.iter().skip(1).fold(boxes[0], |bounds, bounding_box| bounds.union(bounding_box))
+}
+
+// Where the boy is on the nth query: running right, up and down.
+fn boy(query: usize, world_width: i16) -> Rect {
+    let x = (query as i32 * 7 % world_width as i32) as i16;
+    let y = if query % 200 < 100 { 330 } else { 470 };
+    Rect::new_from_x_y(x, y, 60, 110)
+}
+
+// Runs `query` QUERIES times after a warm up, and prints the time per query.
+fn measure(name: &str, mut query: impl FnMut(usize) -> usize) {
+    for n in 0..100 {
+        black_box(query(n));
+    }
+
+    let start = Instant::now();
+    for n in 0..QUERIES {
+        black_box(query(n));
+    }
+    let elapsed = start.elapsed();
+
+    println!("{:<40} {:>8.1} ns/query", name, elapsed.as_nanos() as f64 / QUERIES as f64);
+}
+
+fn main() {
+    for count in [2, 10, 50] {
+        let obstacles = segments(count);
+        let world_width = count * SEGMENT_WIDTH;
+        println!("{} segments, {} obstacles", count, obstacles.len());
+
+        let touched = |index: usize, boy: &Rect| obstacles[index].iter().any(|bounding_box| boy.intersects(bounding_box));
+
+        let linear = |n: usize| {
+            let boy = boy(n, world_width);
+            (0..obstacles.len()).filter(|&index| touched(index, &boy)).count()
+        };
+
+        let mut grid = SpatialGrid::new(256);
+        grid.rebuild(&obstacles.iter().map(|boxes| bounds(boxes)).collect::<Vec<_>>());
+        let mut nearby = vec![];
+        let mut gridded = |n: usize| {
+            let boy = boy(n, world_width);
+            grid.query(&boy, &mut nearby);
+            nearby.iter().filter(|&&index| touched(index, &boy)).count()
+        };
+
+        for n in 0..10_000 {
+            assert_eq!(linear(n), gridded(n), "the grid missed or invented an obstacle");
+        }
+
+        measure("  linear scan", linear);
+        measure("  SpatialGrid", gridded);
+    }
+}
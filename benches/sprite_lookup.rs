@@ -0,0 +1,95 @@
+/*
+What one frame of sprite lookup costs, before and after frames were
+resolved to CellIds at load time.
+
+    cargo bench --bench sprite_lookup
+
+Before, RedHatBoy formatted "Run (n).png" and hashed it into the sheet
+several times a frame. Now the Animation hands out a CellId and the
+lookup is an index into a Vec, which should never allocate once the
+game is running.
+*/
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+use walk_the_dog::engine::{Animation, Cell, CellTable, Clip, Playback, Sheet, SheetRect};
+
+// The system allocator, counting every allocation it makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const FRAMES: usize = 100_000;
+
+fn run_sheet() -> Sheet {
+    let frames = (1..=8)
+        .map(|index| {
+            let rect = SheetRect { x: index * 100, y: 0, w: 100, h: 120 };
+            let cell = Cell { frame: rect.clone(), sprite_source_size: rect, hitboxes: vec![] };
+            (format!("Run ({}).png", index), cell)
+        })
+        .collect::<HashMap<_, _>>();
+
+    Sheet { frames }
+}
+
+// Runs `frame` FRAMES times after a warm up, and returns allocations per frame.
+fn measure(name: &str, mut frame: impl FnMut() -> i16) -> f64 {
+    for _ in 0..100 {
+        black_box(frame());
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        black_box(frame());
+    }
+    let elapsed = start.elapsed();
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / FRAMES as f64;
+
+    println!(
+        "{:<40} {:>8.2} allocations/frame {:>8.1} ns/frame",
+        name,
+        allocations,
+        elapsed.as_nanos() as f64 / FRAMES as f64
+    );
+    allocations
+}
+
+fn main() {
+    let sheet = run_sheet();
+
+    let mut tick: u8 = 0;
+    measure("format! + HashMap<String, Cell>", || {
+        tick = (tick + 1) % 24;
+        let name = format!("Run ({}).png", tick / 3 + 1);
+        sheet.frames.get(&name).map_or(0, |cell| cell.frame.x)
+    });
+
+    let cells = CellTable::new(&sheet).unwrap();
+    let mut animation = Animation::new(vec![Clip::numbered("Run", 8, 3, Playback::Loop)], &cells);
+    let steady_state = measure("Animation + CellTable", || {
+        animation.update();
+        animation.cell().map_or(0, |id| cells.cell(id).frame.x)
+    });
+
+    assert_eq!(steady_state, 0.0, "resolved sprite lookups should not allocate");
+}
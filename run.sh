@@ -6,7 +6,7 @@
 CMD=""
 FLAG="_"
 FLAG=$1
-DEBUG="build --debug --target web --out-dir www/pkg"
+DEBUG="build --debug --target web --out-dir www/pkg -- --features debug-overlay"
 TEST="test --headless --firefox"
 RUN="build --target web --out-dir www/pkg"
 
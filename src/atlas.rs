@@ -0,0 +1,372 @@
+/*
+Texture atlas packing for the native asset tools.
+
+Takes a directory of PNG frames, trims the transparent border off each one
+and packs them into a single image. The JSON that goes with it is written
+straight from engine::Sheet, so it's always in the format the game reads:
+
+{"frames": {
+  "Run (1).png": {
+    "frame": {"x":0,"y":0,"w":71,"h":115},
+    "spriteSourceSize": {"x":58,"y":8,"w":71,"h":115}
+  }
+}}
+
+`frame` is where the trimmed pixels are in the atlas, `spriteSourceSize` is
+where they sat in the original frame, which is how RedHatBoy keeps frames of
+different sizes lined up. Hitboxes can't come from the PNGs, so repacking
+keeps the ones the old JSON had, see keep_hitboxes.
+*/
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use crate::engine::{Cell, Sheet, SheetRect};
+
+// Gap between frames, so filtering never bleeds one frame into the next.
+const PADDING: u32 = 2;
+
+// An RGBA image, 4 bytes per pixel.
+pub struct Frame {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    fn alpha(&self, x: u32, y: u32) -> u8 {
+        self.pixels[((y * self.width + x) * 4 + 3) as usize]
+    }
+
+    fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Frame {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in y..y + height {
+            let start = ((row * self.width + x) * 4) as usize;
+            pixels.extend_from_slice(&self.pixels[start..start + (width * 4) as usize]);
+        }
+
+        Frame {
+            name: self.name.clone(),
+            width,
+            height,
+            pixels,
+        }
+    }
+}//^-- impl Frame
+
+pub struct Atlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    pub sheet: Sheet,
+}
+
+/*
+Cuts away fully transparent rows and columns. The returned rect is the
+trimmed area's offset and size inside the original frame, which is what
+spriteSourceSize holds. A frame with nothing visible keeps a single pixel.
+*/
+pub fn trim(frame: &Frame) -> (Frame, SheetRect) {
+    let visible = |x: u32, y: u32| frame.alpha(x, y) > 0;
+
+    let rows: Vec<u32> = (0..frame.height)
+                            .filter(|&y| (0..frame.width).any(|x| visible(x, y)))
+                            .collect();
+    let columns: Vec<u32> = (0..frame.width)
+                            .filter(|&x| (0..frame.height).any(|y| visible(x, y)))
+                            .collect();
+
+    let (left, top, width, height) = match (rows.first(), rows.last(), columns.first(), columns.last()) {
+        (Some(&top), Some(&bottom), Some(&left), Some(&right)) =>
+            (left, top, right - left + 1, bottom - top + 1),
+        _ => (0, 0, 1.min(frame.width), 1.min(frame.height)),
+    };
+
+    (
+        frame.crop(left, top, width, height),
+        SheetRect {
+            x: left as i16,
+            y: top as i16,
+            w: width as i16,
+            h: height as i16,
+        },
+    )
+}//^-- fn trim
+
+/*
+Shelf packing: tallest frames first, left to right, starting a new shelf
+when a row is full. The atlas is roughly square, but never narrower than
+the widest frame.
+*/
+pub fn layout(sizes: &[(u32, u32)]) -> (u32, u32, Vec<(u32, u32)>) {
+    let area: u32 = sizes
+                        .iter()
+                        .map(|(w, h)| (w + PADDING) * (h + PADDING))
+                        .sum();
+    let widest = sizes.iter().map(|(w, _)| w + PADDING).max().unwrap_or(0);
+    let width = widest.max(((area as f64).sqrt() * 1.1).ceil() as u32);
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1).then(a.cmp(&b)));
+
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for index in order {
+        let (w, h) = sizes[index];
+        if x + w > width {
+            x = 0;
+            y += shelf_height + PADDING;
+            shelf_height = 0;
+        }
+        positions[index] = (x, y);
+        x += w + PADDING;
+        shelf_height = shelf_height.max(h);
+    }
+
+    (width.max(1), (y + shelf_height).max(1), positions)
+}//^-- fn layout
+
+pub fn pack(frames: &[Frame]) -> Result<Atlas> {
+    let trimmed: Vec<(Frame, SheetRect)> = frames.iter().map(trim).collect();
+    let sizes: Vec<(u32, u32)> = trimmed
+                                    .iter()
+                                    .map(|(frame, _)| (frame.width, frame.height))
+                                    .collect();
+    let (width, height, positions) = layout(&sizes);
+
+    if width > i16::MAX as u32 || height > i16::MAX as u32 {
+        return Err(anyhow!("Atlas of {}x{} is too big for the sheet format", width, height));
+    }
+
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    let mut cells = HashMap::new();
+    for ((frame, source), (x, y)) in trimmed.into_iter().zip(positions) {
+        for row in 0..frame.height {
+            let from = (row * frame.width * 4) as usize;
+            let to = (((y + row) * width + x) * 4) as usize;
+            let len = (frame.width * 4) as usize;
+            pixels[to..to + len].copy_from_slice(&frame.pixels[from..from + len]);
+        }
+
+        cells.insert(
+            frame.name.clone(),
+            Cell {
+                frame: SheetRect {
+                    x: x as i16,
+                    y: y as i16,
+                    w: frame.width as i16,
+                    h: frame.height as i16,
+                },
+                sprite_source_size: source,
+                hitboxes: vec![],
+            },
+        );
+    }
+
+    Ok(Atlas {
+        width,
+        height,
+        pixels,
+        sheet: Sheet { frames: cells },
+    })
+}//^-- fn pack
+
+// The sheet as JSON, frames sorted by name so the output diffs cleanly.
+pub fn sheet_json(sheet: &Sheet) -> Result<String> {
+    #[derive(Serialize)]
+    struct SortedSheet<'a> {
+        frames: BTreeMap<&'a String, &'a Cell>,
+    }
+
+    serde_json::to_string_pretty(&SortedSheet {
+        frames: sheet.frames.iter().collect(),
+    })
+    .map_err(|err| anyhow!("Could not serialize sheet {:#?}", err))
+}
+
+/*
+Copies each cell's hitboxes over from `previous` by name. They're in the
+untrimmed frame's coordinates, so they're still right however the frame
+was trimmed and wherever it was packed.
+*/
+pub fn keep_hitboxes(sheet: &mut Sheet, previous: &Sheet) {
+    for (name, cell) in sheet.frames.iter_mut() {
+        if let Some(old) = previous.frames.get(name) {
+            cell.hitboxes = old.hitboxes.clone();
+        }
+    }
+}
+
+pub fn load_frames(directory: &Path) -> Result<Vec<Frame>> {
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .map_err(|err| anyhow!("Could not read {}: {}", directory.display(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    paths.sort();
+
+    paths.iter().map(|path| load_png(path)).collect()
+}
+
+pub fn load_png(path: &Path) -> Result<Frame> {
+    let file = File::open(path).map_err(|err| anyhow!("Could not open {}: {}", path.display(), err))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+                        .read_info()
+                        .map_err(|err| anyhow!("Could not decode {}: {}", path.display(), err))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+                .next_frame(&mut buffer)
+                .map_err(|err| anyhow!("Could not decode {}: {}", path.display(), err))?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return Err(anyhow!("Palette was not expanded in {}", path.display())),
+    };
+
+    Ok(Frame {
+        name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("No file name in {}", path.display()))?,
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}//^-- fn load_png
+
+pub fn save_png(atlas: &Atlas, path: &Path) -> Result<()> {
+    let file = File::create(path).map_err(|err| anyhow!("Could not create {}: {}", path.display(), err))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), atlas.width, atlas.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&atlas.pixels))
+        .map_err(|err| anyhow!("Could not write {}: {}", path.display(), err))
+}
+
+// Everything the pack_atlas binary does. Returns how many frames were packed.
+pub fn pack_directory(frames_directory: &Path, image_path: &Path, json_path: &Path) -> Result<usize> {
+    let frames = load_frames(frames_directory)?;
+    if frames.is_empty() {
+        return Err(anyhow!("No PNG frames found in {}", frames_directory.display()));
+    }
+
+    let mut atlas = pack(&frames)?;
+    let previous = fs::read_to_string(json_path)
+                        .ok()
+                        .and_then(|json| serde_json::from_str::<Sheet>(&json).ok());
+    if let Some(previous) = previous {
+        keep_hitboxes(&mut atlas.sheet, &previous);
+    }
+
+    save_png(&atlas, image_path)?;
+    fs::write(json_path, sheet_json(&atlas.sheet)?)
+        .map_err(|err| anyhow!("Could not write {}: {}", json_path.display(), err))?;
+
+    Ok(frames.len())
+}
+
+//======================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A transparent frame with an opaque rectangle drawn in it.
+    fn frame_with_box(name: &str, size: (u32, u32), rect: (u32, u32, u32, u32)) -> Frame {
+        let (width, height) = size;
+        let (left, top, w, h) = rect;
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        for y in top..top + h {
+            for x in left..left + w {
+                let i = ((y * width + x) * 4) as usize;
+                pixels[i..i + 4].copy_from_slice(&[255, 0, 0, 255]);
+            }
+        }
+
+        Frame {
+            name: name.to_string(),
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn trim_records_the_offset_into_the_original_frame() {
+        let frame = frame_with_box("Run (1).png", (160, 136), (58, 8, 71, 115));
+        let (trimmed, source) = trim(&frame);
+
+        assert_eq!((trimmed.width, trimmed.height), (71, 115));
+        assert_eq!((source.x, source.y, source.w, source.h), (58, 8, 71, 115));
+        assert!(trimmed.pixels.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn packed_frames_do_not_overlap_and_round_trip_as_a_sheet() {
+        let frames = vec![
+            frame_with_box("a.png", (40, 40), (5, 5, 30, 20)),
+            frame_with_box("b.png", (50, 60), (0, 0, 50, 60)),
+            frame_with_box("c.png", (20, 20), (10, 0, 10, 10)),
+            frame_with_box("d.png", (10, 10), (0, 0, 0, 0)),
+        ];
+        let atlas = pack(&frames).unwrap();
+
+        let json = sheet_json(&atlas.sheet).unwrap();
+        let sheet: Sheet = serde_json::from_str(&json).unwrap();
+        assert_eq!(sheet.frames.len(), 4);
+
+        let rects: Vec<_> = sheet.frames.values().map(|cell| cell.frame.clone()).collect();
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.x + a.w <= atlas.width as i16 && a.y + a.h <= atlas.height as i16);
+            for b in rects.iter().skip(i + 1) {
+                let overlaps = a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h;
+                assert!(!overlaps);
+            }
+        }
+
+        let a = &sheet.frames["a.png"];
+        assert_eq!((a.sprite_source_size.x, a.sprite_source_size.y), (5, 5));
+        assert_eq!((a.frame.w, a.frame.h), (30, 20));
+        assert!(!json.contains("hitboxes"));
+    }
+
+    #[test]
+    fn repacking_keeps_hitboxes_from_the_old_sheet() {
+        let previous: Sheet = serde_json::from_str(r#"{"frames": {"a.png": {
+            "frame": {"x":0,"y":0,"w":30,"h":20},
+            "spriteSourceSize": {"x":5,"y":5,"w":30,"h":20},
+            "hitboxes": [{"name": "body", "x": 8, "y": 6, "w": 20, "h": 15}]
+        }}}"#).unwrap();
+
+        let mut atlas = pack(&[
+            frame_with_box("a.png", (40, 40), (5, 5, 30, 20)),
+            frame_with_box("b.png", (10, 10), (0, 0, 10, 10)),
+        ]).unwrap();
+        keep_hitboxes(&mut atlas.sheet, &previous);
+
+        let sheet: Sheet = serde_json::from_str(&sheet_json(&atlas.sheet).unwrap()).unwrap();
+        let body = &sheet.frames["a.png"].hitboxes[0];
+        assert_eq!(body.name, "body");
+        let rect = body.at(crate::engine::Point { x: 100, y: 200 });
+        assert_eq!((rect.x(), rect.y(), rect.width, rect.height), (108, 206, 20, 15));
+        assert!(sheet.frames["b.png"].hitboxes.is_empty());
+    }
+}//^-- mod tests
@@ -0,0 +1,37 @@
+/*
+Packs a directory of PNG frames into a sprite sheet the game can load.
+
+    cargo run --bin pack_atlas -- <frames directory> <atlas.png> <atlas.json>
+
+e.g. to rebuild the Red Hat Boy sheet from its frames:
+
+    cargo run --bin pack_atlas -- art/rhb www/resources/pix/rhb.png www/resources/pix/rhb.json
+
+Frames are named after their file, so "Run (1).png" in the directory becomes
+the "Run (1).png" cell in the sheet.
+*/
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use std::{env, path::Path, process};
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("Usage: {} <frames directory> <atlas.png> <atlas.json>", args[0]);
+        process::exit(2);
+    }
+
+    match walk_the_dog::atlas::pack_directory(
+        Path::new(&args[1]),
+        Path::new(&args[2]),
+        Path::new(&args[3]),
+    ) {
+        Ok(count) => println!("Packed {} frames into {} and {}", count, args[2], args[3]),
+        Err(err) => {
+            eprintln!("{:#}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,16 @@
+/*
+Prints the game's state machines as Graphviz DOT.
+
+    cargo run --bin state_diagrams > docs/state_machines.dot
+    dot -Tsvg -O docs/state_machines.dot
+
+The tests fail until docs/state_machines.dot is regenerated after a
+transition table changes.
+*/
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    print!("{}", walk_the_dog::diagrams::state_machines_dot());
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
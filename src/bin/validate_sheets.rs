@@ -0,0 +1,32 @@
+/*
+Checks the sprite sheets contain every cell the game asks for.
+
+    cargo run --bin validate_sheets -- [rhb.json] [tiles.json]
+
+Defaults to the sheets in www/resources/pix. Prints a report per sheet
+and exits with an error if anything is missing.
+*/
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use std::{env, process};
+
+    let args: Vec<String> = env::args().collect();
+    let rhb = args.get(1).map_or("www/resources/pix/rhb.json", String::as_str);
+    let tiles = args.get(2).map_or("www/resources/pix/tiles.json", String::as_str);
+
+    match walk_the_dog::validation::validate_sheets(rhb.as_ref(), tiles.as_ref()) {
+        Ok(reports) => {
+            reports.iter().for_each(|report| println!("{}", report));
+            if !reports.iter().all(|report| report.is_ok()) {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("{:#}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -148,6 +148,69 @@ pub fn now() -> Result<f64> {
 }
 
 
+// Resolves after `millis`, through setTimeout.
+pub async fn sleep(millis: i32) -> Result<()> {
+    let window = window()?;
+    let mut timer_error = None;
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        if let Err(err) = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis) {
+            timer_error = Some(err);
+        }
+    });
+    if let Some(err) = timer_error {
+        return Err(anyhow!("Could not set a timeout {:#?}", err));
+    }
+
+    JsFuture::from(promise)
+        .await
+        .map(|_value| ())
+        .map_err(|err| anyhow!("Timeout failed {:#?}", err))
+}
+
+pub fn device_pixel_ratio() -> Result<f64> {
+    Ok(window()?.device_pixel_ratio())
+}
+
+// The size the page lays the canvas out at, in CSS pixels.
+pub fn canvas_css_size() -> Result<(f64, f64)> {
+    let canvas = canvas()?;
+    Ok((canvas.client_width().into(), canvas.client_height().into()))
+}
+
+// Resizes the backing store, which also resets the context's state.
+pub fn resize_canvas(width: u32, height: u32) -> Result<()> {
+    let canvas = canvas()?;
+    canvas.set_width(width);
+    canvas.set_height(height);
+    Ok(())
+}
+
+/*
+Lays the UI over the letterboxed game area, scaled like the canvas,
+so its buttons keep lining up with what is drawn underneath.
+*/
+pub fn fit_ui(left: f64, top: f64, width: f64, height: f64, scale: f64) -> Result<()> {
+    let style = find_ui()?
+                    .dyn_into::<HtmlElement>()
+                    .map_err(|err| anyhow!("Could not cast into HtmlElement {:#?}", err))?
+                    .style();
+
+    [
+        ("left", format!("{}px", left)),
+        ("top", format!("{}px", top)),
+        ("width", format!("{}px", width)),
+        ("height", format!("{}px", height)),
+        ("transform-origin", "0 0".to_string()),
+        ("transform", format!("scale({})", scale)),
+    ]
+    .iter()
+    .try_for_each(|(property, value)| {
+        style
+            .set_property(property, value)
+            .map_err(|err| anyhow!("Could not set {} on the UI {:#?}", property, err))
+    })
+}
+
 //------------- UI
 pub fn draw_ui(html: &str) -> Result<()> {
     find_ui()?
@@ -202,7 +265,7 @@ mod tests {
     #[wasm_bindgen_test]
     async fn test_error_loading_json() {
         let json = fetch_json("not_there.json").await;
-        assert_eq!(json.is_err(), true);
+        assert!(json.is_err());
     }
 }
 
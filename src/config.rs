@@ -0,0 +1,141 @@
+/*
+The numbers that decide how the game feels, loaded from
+resources/config.json so they can be tuned without rebuilding.
+Anything left out of the JSON keeps its default, and the defaults
+are what the game shipped with.
+
+Debug builds keep fetching the file and hand every change to the
+running game, see watch.
+*/
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use gloo_utils::format::JsValueSerdeExt;
+use serde::{Deserialize, Serialize};
+
+use crate::browser;
+
+pub const GAME_CONFIG: &str = "../resources/config.json";
+// How often a debug build checks the file for changes.
+const WATCH_INTERVAL_MS: i32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GameConfig {
+    // The boy
+    pub gravity: i16,
+    pub jump_speed: i16,
+    pub terminal_velocity: i16,
+    pub running_speed: i16,
+    pub floor: i16,
+    pub starting_point: i16,
+    // 1, the default, is the original game: the first hit ends it.
+    pub lives: i16,
+    pub invulnerable_ticks: i16,
+    // 0 air jumps or 0 air control, the defaults, turns them off.
+    pub air_jumps: i16,
+    pub double_jump_speed: i16,
+    pub air_control: i16,
+    pub air_speed_min: i16,
+    pub air_speed_max: i16,
+    // The obstacles
+    pub timeline_minimum: i16,
+    pub obstacle_buffer: i16,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            gravity: 1,
+            jump_speed: -27,
+            terminal_velocity: 18,
+            running_speed: 3,
+            floor: 479,
+            starting_point: -20,
+            lives: 1,
+            invulnerable_ticks: 90,
+            air_jumps: 0,
+            double_jump_speed: -14,
+            air_control: 0,
+            air_speed_min: 1,
+            air_speed_max: 6,
+            timeline_minimum: 1000,
+            obstacle_buffer: 20,
+        }
+    }
+}
+
+impl GameConfig {
+    pub async fn fetch(source: &str) -> Result<GameConfig> {
+        let json = browser::fetch_json(source).await?;
+        JsValueSerdeExt::into_serde(&json)
+            .map_err(|err| anyhow!("Could not parse {} {:#?}", source, err))
+    }
+
+    // Falls back to the defaults, a broken config shouldn't stop the game.
+    pub async fn load(source: &str) -> GameConfig {
+        GameConfig::fetch(source).await.unwrap_or_else(|err| {
+            error!("Using the default config {:#?}", err);
+            GameConfig::default()
+        })
+    }
+}//^-- impl GameConfig
+
+/*
+Re-fetches the config every second and sends it whenever it differs from
+the last one. The query string keeps the browser from answering from its
+cache. Stops when the receiver is dropped.
+*/
+pub fn watch(source: &str, current: GameConfig) -> UnboundedReceiver<GameConfig> {
+    let (sender, receiver) = unbounded();
+    let source = source.to_string();
+
+    browser::spawn_local(async move {
+        let mut current = current;
+        loop {
+            if let Err(err) = browser::sleep(WATCH_INTERVAL_MS).await {
+                error!("Stopped watching {} {:#?}", source, err);
+                return;
+            }
+
+            let uncached = format!("{}?{}", source, browser::now().unwrap_or_default());
+            match GameConfig::fetch(&uncached).await {
+                Ok(config) if config != current => {
+                    log!("Reloaded {}", source);
+                    current = config;
+                    if sender.unbounded_send(config).is_err() {
+                        return;
+                    }
+                }
+                Ok(_unchanged) => (),
+                Err(err) => { log!("Keeping the current config {:#?}", err); }
+            }
+        }
+    });
+
+    receiver
+}//^-- fn watch
+
+//======================================
+
+// serde_json is only a native dependency.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_values_keep_their_defaults() {
+        let config: GameConfig = serde_json::from_str(r#"{"gravity": 2, "jump_speed": -30}"#).unwrap();
+
+        assert_eq!(config.gravity, 2);
+        assert_eq!(config.jump_speed, -30);
+        assert_eq!(config.floor, GameConfig::default().floor);
+    }
+
+    #[test]
+    fn shipped_config_matches_the_defaults() {
+        let json = include_str!("../www/resources/config.json");
+        let config: GameConfig = serde_json::from_str(json).unwrap();
+
+        assert_eq!(config, GameConfig::default());
+    }
+}//^-- mod tests
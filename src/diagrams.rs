@@ -0,0 +1,31 @@
+/*
+The game's state machines as Graphviz, for the design docs.
+
+Drawn from the same transition tables the state machine tests check,
+and docs/state_machines.dot is held to this output, so the diagrams
+in the docs are the machines the game runs.
+*/
+use crate::game::{RED_HAT_BOY_TRANSITIONS, WALK_THE_DOG_TRANSITIONS};
+
+// Both machines, one digraph after the other.
+pub fn state_machines_dot() -> String {
+    [&WALK_THE_DOG_TRANSITIONS, &RED_HAT_BOY_TRANSITIONS]
+        .iter()
+        .map(|table| table.to_dot())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+//======================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_diagrams_match_the_transition_tables() {
+        let shipped = include_str!("../docs/state_machines.dot");
+
+        assert_eq!(shipped, state_machines_dot(), "regenerate with: cargo run --bin state_diagrams > docs/state_machines.dot");
+    }
+}//^-- mod tests
@@ -7,21 +7,61 @@ use async_trait::async_trait;
 use futures::channel::{
     mpsc::{unbounded, UnboundedReceiver},
     oneshot::channel,};
-use serde::Deserialize;
+use futures::{future, TryFutureExt};
+use rand::{thread_rng, Rng};
+use gloo_utils::format::JsValueSerdeExt;
+use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Mutex};
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
 use web_sys::{CanvasRenderingContext2d, HtmlImageElement, HtmlElement};
 use web_sys::{AudioContext, AudioBuffer,};
+use js_sys::ArrayBuffer;
 
 //use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, Default)]
+/*
+The errors the engine knows how to explain to the player.
+They travel inside anyhow::Error like everything else, and whoever
+shows the error screen downcasts to find out what went wrong.
+*/
+#[derive(Debug)]
+pub enum EngineError {
+    MissingAsset { key: String, source: String },
+    DecodeFailure { key: String, source: String },
+    CanvasMissing,
+    AudioUnavailable,
+    // Every asset that failed, so the player sees them all at once.
+    AssetsFailed(Vec<EngineError>),
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::MissingAsset { key, source } => 
+                write!(f, "Could not load asset '{}' from {}", key, source),
+            EngineError::DecodeFailure { key, source } => 
+                write!(f, "Could not decode asset '{}' from {}", key, source),
+            EngineError::CanvasMissing => 
+                write!(f, "No canvas to draw the game on"),
+            EngineError::AudioUnavailable => 
+                write!(f, "Audio is not available in this browser"),
+            EngineError::AssetsFailed(errors) => {
+                let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{} assets failed: {}", errors.len(), messages.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
 }
 
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -76,10 +116,120 @@ impl Rect {
     pub fn set_x(&mut self, x: i16) {
         self.position.x = x
     }
+
+    /*
+    How far self is into `other` on each axis, and the side of `other`
+    it's nearest to getting out through, or None if they don't touch.
+    */
+    pub fn penetration(&self, other: &Rect) -> Option<(Point, Side)> {
+        if !self.intersects(other) {
+            return None;
+        }
+
+        let depth = Point {
+            x: self.right().min(other.right()) - self.x().max(other.x()),
+            y: self.bottom().min(other.bottom()) - self.y().max(other.y()),
+        };
+        let (horizontal, vertical) = self.sides_of(other);
+        let side = if depth.x < depth.y { horizontal } else { vertical };
+
+        Some((depth, side))
+    }
+
+    // Which side of `other` self's centre is on, across and down.
+    fn sides_of(&self, other: &Rect) -> (Side, Side) {
+        // Centres doubled, to stay in whole numbers, and in i32 because
+        // doubling overflows i16 well before the origin is shifted back.
+        let centre = |start: i16, length: i16| i32::from(start) * 2 + i32::from(length);
+        let horizontal = if centre(self.x(), self.width) < centre(other.x(), other.width) { Side::Left } else { Side::Right };
+        let vertical = if centre(self.y(), self.height) < centre(other.y(), other.height) { Side::Top } else { Side::Bottom };
+        (horizontal, vertical)
+    }
+
+    /*
+    Swept AABB: moves self by `velocity` over one tick and finds when it
+    first touches `other`, as a fraction of the tick, and which side of
+    `other` it hits. Unlike intersects this can't miss a box that's
+    thinner than the distance moved. A rect that starts out overlapping
+    hits at time 0, on the side it's nearest to getting out through,
+    unless it's already moving out that way, which is how something
+    resting a pixel deep in a platform walks off the end of it.
+    */
+    pub fn sweep(&self, velocity: Point, other: &Rect) -> Option<Sweep> {
+        if let Some((_depth, side)) = self.penetration(other) {
+            let (horizontal, vertical) = self.sides_of(other);
+            let normal = match side {
+                Side::Left if velocity.x < 0 => vertical,
+                Side::Right if velocity.x > 0 => vertical,
+                Side::Top if velocity.y < 0 => horizontal,
+                Side::Bottom if velocity.y > 0 => horizontal,
+                side => side,
+            };
+            return Some(Sweep { time: 0.0, normal });
+        }
+
+        // When each axis starts and stops overlapping, in ticks.
+        let axis = |start: i16, end: i16, other_start: i16, other_end: i16, speed: i16| {
+            if speed > 0 {
+                Some(((other_start - end) as f32 / speed as f32, (other_end - start) as f32 / speed as f32))
+            } else if speed < 0 {
+                Some(((other_end - start) as f32 / speed as f32, (other_start - end) as f32 / speed as f32))
+            } else if start < other_end && other_start < end {
+                Some((f32::NEG_INFINITY, f32::INFINITY))
+            } else {
+                None
+            }
+        };
+        let (x_entry, x_exit) = axis(self.x(), self.right(), other.x(), other.right(), velocity.x)?;
+        let (y_entry, y_exit) = axis(self.y(), self.bottom(), other.y(), other.bottom(), velocity.y)?;
+
+        let entry = x_entry.max(y_entry);
+        let exit = x_exit.min(y_exit);
+        // Touching without going in is left to the next tick.
+        if entry >= exit || !(0.0..1.0).contains(&entry) {
+            return None;
+        }
+
+        let normal = if x_entry > y_entry {
+            if velocity.x > 0 { Side::Left } else { Side::Right }
+        } else if velocity.y > 0 {
+            Side::Top
+        } else {
+            Side::Bottom
+        };
+
+        Some(Sweep { time: entry, normal })
+    }//^-- fn sweep
+
+    // The smallest rect covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x().min(other.x());
+        let y = self.y().min(other.y());
+        Rect::new_from_x_y(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
 }//^-- impl Rect
 
-#[derive(Deserialize, Clone)]
-//#[derive(Serialize, Deserialize, Clone)]
+// Where a sweep hit: `time` is 0.0 to 1.0 through the tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    pub time: f32,
+    pub normal: Side,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SheetRect {
     pub x: i16,
     pub y: i16,
@@ -88,35 +238,143 @@ pub struct SheetRect {
 }
 
 
-#[derive(Deserialize, Clone)]
-//#[derive(Serialize, Deserialize, Clone)]
+/*
+Cells can also carry named boxes to collide with, which the sheet JSON
+lists after the usual fields:
+
+  "hitboxes": [{"name": "body", "x": 63, "y": 73, "w": 57, "h": 55}]
+
+They're in the untrimmed frame's coordinates, like spriteSourceSize, so
+they stay put when the sheet is repacked. Cells without any leave it to
+the game to work out a box.
+*/
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Cell {
     pub frame: SheetRect,
     pub sprite_source_size: SheetRect,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hitboxes: Vec<Hitbox>,
 }
 
-#[derive(Deserialize, Clone)]
-//#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Hitbox {
+    pub name: String,
+    #[serde(flatten)]
+    pub rect: SheetRect,
+}
+
+impl Hitbox {
+    // Where it is when its frame is drawn at `position`.
+    pub fn at(&self, position: Point) -> Rect {
+        Rect::new_from_x_y(position.x + self.rect.x, position.y + self.rect.y, self.rect.w, self.rect.h)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Sheet {
     pub frames: HashMap<String, Cell>,
 }
 
+// Where a cell lives in a CellTable. Looking one up is just indexing a Vec.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellId(u16);
+
+/*
+The cells of a Sheet in a Vec, plus the names to find them by.
+Names are resolved to CellIds once, when something is loaded, so drawing
+never has to hash a String.
+*/
+pub struct CellTable {
+    cells: Vec<Cell>,
+    ids: HashMap<String, CellId>,
+}
+
+impl CellTable {
+    // Fails for sheets with more frames than a CellId can number.
+    pub fn new(sheet: &Sheet) -> Result<Self> {
+        let mut names: Vec<&String> = sheet.frames.keys().collect();
+        names.sort();
+
+        let ids = names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| {
+                        u16::try_from(index)
+                            .map(|index| (name.to_string(), CellId(index)))
+                            .map_err(|err| anyhow!("Sheet has {} frames, more than {} {:#?}", names.len(), u16::MAX as usize + 1, err))
+                    })
+                    .collect::<Result<_>>()?;
+
+        Ok(CellTable {
+            cells: names.iter().map(|name| sheet.frames[*name].clone()).collect(),
+            ids,
+        })
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<CellId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn cell(&self, id: CellId) -> &Cell {
+        &self.cells[id.0 as usize]
+    }
+}//^-- impl CellTable
+
+
+/*
+Which of the cells a piece of game code relies on are missing from a sheet.
+Built once at load time, so a typo in a frame name shows up as one clear
+report instead of a placeholder every frame.
+*/
+pub struct SheetReport {
+    pub name: String,
+    pub missing: Vec<String>,
+}
+
+impl SheetReport {
+    pub fn check<'a>(name: &str, sheet: &Sheet, cells: impl IntoIterator<Item = &'a str>) -> Self {
+        SheetReport {
+            name: name.to_string(),
+            missing: cells
+                        .into_iter()
+                        .filter(|cell| !sheet.frames.contains_key(*cell))
+                        .map(|cell| cell.to_string())
+                        .collect(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+impl std::fmt::Display for SheetReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            write!(f, "{}: ok", self.name)
+        } else {
+            write!(f, "{}: {} missing cell(s): {}", 
+                   self.name, self.missing.len(), self.missing.join(", "))
+        }
+    }
+}
 
 pub struct Image {
-    element: HtmlImageElement,
+    // Shared with the RenderQueue, see DrawCommand.
+    element: Rc<HtmlImageElement>,
     bounding_box: Rect,
 }
 
 impl Image {
     pub fn new(element: HtmlImageElement, position: Point) -> Self {
         let bounding_box = Rect {
-            position: Point{x: position.x.into(), y: position.y.into()},
+            position: Point{x: position.x, y: position.y},
             width: element.width() as i16, 
             height: element.height() as i16,
         };
         Self {
-            element,
+            element: Rc::new(element),
             bounding_box,
         }
     }
@@ -125,13 +383,12 @@ impl Image {
         &self.bounding_box
     }
 
-    pub fn draw(&self, renderer: &Renderer) {
-        renderer.draw_entire_image(&self.element, &self.bounding_box.position);
+    pub fn draw(&self, queue: &mut RenderQueue, layer: Layer) {
+        queue.draw_entire_image(layer, &self.element, &self.bounding_box.position);
     }
 
-    #[allow(dead_code)]
-    pub fn draw_rect(&self, renderer: &Renderer) {
-        renderer.draw_rect(self.bounding_box());
+    pub fn draw_rect(&self, queue: &mut RenderQueue) {
+        queue.draw_rect(Layer::Hud, self.bounding_box());
     }
 
     pub fn move_horizontally(&mut self, distance: i16) {
@@ -146,30 +403,948 @@ impl Image {
     }
 }
 
+//------------------- PARALLAX -------------------------
+
+/*
+One strip of a scrolling background. Its offset is how far it has scrolled,
+kept modulo the image width when it tiles, so it's unaffected by the world's
+origin moving and never grows.
+*/
+pub struct ParallaxLayer {
+    image: Rc<HtmlImageElement>,
+    width: i16,
+    scroll_factor: f32,
+    y: i16,
+    tiled: bool,
+    offset: f32,
+}
+
+impl ParallaxLayer {
+    // A scroll factor of 1.0 moves with the world, 0.0 stays put.
+    pub fn new(image: HtmlImageElement, scroll_factor: f32, y: i16, tiled: bool) -> Self {
+        ParallaxLayer {
+            width: image.width() as i16,
+            image: Rc::new(image),
+            scroll_factor,
+            y,
+            tiled,
+            offset: 0.0,
+        }
+    }
+}
+
+// Layers back to front, drawn in screen space behind the world.
+pub struct ParallaxBackground {
+    layers: Vec<ParallaxLayer>,
+}
+
+impl ParallaxBackground {
+    pub fn new(layers: Vec<ParallaxLayer>) -> Self {
+        ParallaxBackground { layers }
+    }
+
+    // `distance` is how far the camera moved since the last scroll.
+    pub fn scroll(&mut self, distance: i16) {
+        self.layers.iter_mut().for_each(|layer| {
+            layer.offset = scrolled(
+                layer.offset,
+                distance as f32 * layer.scroll_factor,
+                layer.width,
+                layer.tiled,
+            );
+        });
+    }
+
+    pub fn reset(&mut self) {
+        self.layers.iter_mut().for_each(|layer| layer.offset = 0.0);
+    }
+
+    pub fn draw(&self, queue: &mut RenderQueue, view_width: i16) {
+        for layer in self.layers.iter() {
+            for x in tile_positions(layer.offset, layer.width, view_width, layer.tiled) {
+                queue.draw_entire_image(Layer::Background, &layer.image, &Point { x, y: layer.y });
+            }
+        }
+    }
+}//^-- impl ParallaxBackground
+
+fn scrolled(offset: f32, distance: f32, width: i16, tiled: bool) -> f32 {
+    if tiled && width > 0 {
+        (offset + distance).rem_euclid(width as f32)
+    } else {
+        offset + distance
+    }
+}
+
+// Where to draw each copy of a layer so copies cover 0..view_width.
+fn tile_positions(offset: f32, width: i16, view_width: i16, tiled: bool) -> impl Iterator<Item = i16> {
+    let first = -(offset.floor() as i16);
+    let count = match (tiled, width > 0) {
+        (false, _) => 1,
+        (true, false) => 0,
+        (true, true) => (view_width - first + width - 1) / width,
+    };
+    (0..count).map(move |i| first + i * width)
+}
+
 pub struct SpriteSheet {
     sheet: Sheet,
-    image: HtmlImageElement,
+    cells: CellTable,
+    image: Rc<HtmlImageElement>,
 }
 
 impl SpriteSheet {
-    pub fn new(sheet: Sheet, image: HtmlImageElement) -> Self { 
-        SpriteSheet { sheet, image } 
+    pub fn new(sheet: Sheet, image: HtmlImageElement) -> Result<Self> { 
+        let cells = CellTable::new(&sheet)?;
+        Ok(SpriteSheet { sheet, cells, image: Rc::new(image) })
     }
     
     pub fn cell(&self, name: &str) -> Option<&Cell> {
         self.sheet.frames.get(name)
     }
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
-        renderer.draw_image(&self.image, source, destination);
+    pub fn cells(&self) -> &CellTable {
+        &self.cells
+    }
+    pub fn sheet(&self) -> &Sheet {
+        &self.sheet
+    }
+    pub fn draw(&self, 
+                queue: &mut RenderQueue, 
+                layer: Layer,
+                source: &Rect, 
+                destination: &Rect, 
+                params: &DrawParams) {
+        queue.draw_image(layer, &self.image, source, destination, params);
     }
 }//^-- impl SpriteSheet
 
 
+//------------------- ANIMATION -------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Playback {
+    Loop,
+    Once,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationEvent {
+    // A looping clip went back to its first frame.
+    Looped,
+    // A one-shot clip reached its end. It holds its last frame from here on.
+    Finished,
+}
+
+// A named run of sheet cells, each shown for the same number of ticks.
+pub struct Clip {
+    name: String,
+    frames: Vec<String>,
+    // `frames` resolved against the sheet, None where a cell is missing.
+    cells: Vec<Option<CellId>>,
+    ticks_per_frame: u16,
+    playback: Playback,
+}
+
+impl Clip {
+    pub fn new(name: &str, frames: Vec<String>, ticks_per_frame: u16, playback: Playback) -> Self {
+        Clip {
+            name: name.to_string(),
+            cells: vec![None; frames.len()],
+            frames,
+            ticks_per_frame: ticks_per_frame.max(1),
+            playback,
+        }
+    }
+
+    // The TexturePacker naming our sheets use: "Run (1).png" to "Run (8).png".
+    pub fn numbered(name: &str, count: u16, ticks_per_frame: u16, playback: Playback) -> Self {
+        let frames = (1..=count).map(|index| format!("{} ({}).png", name, index)).collect();
+        Clip::new(name, frames, ticks_per_frame, playback)
+    }
+
+    pub fn frames(&self) -> &[String] {
+        &self.frames
+    }
+
+    fn duration(&self) -> u16 {
+        self.frames.len() as u16 * self.ticks_per_frame
+    }
+}//^-- impl Clip
+
+/*
+Plays one clip at a time out of a fixed set. The clips are shared,
+so cloning an Animation only copies where it is in the current clip.
+*/
+#[derive(Clone)]
+pub struct Animation {
+    clips: Rc<Vec<Clip>>,
+    current: Option<usize>,
+    tick: u16,
+    finished: bool,
+    held: bool,
+}
+
+impl Animation {
+    // Starts out playing the first clip, with every frame 
+    // looked up in `cells` now rather than while drawing.
+    pub fn new(mut clips: Vec<Clip>, cells: &CellTable) -> Self {
+        for clip in clips.iter_mut() {
+            clip.cells = clip.frames.iter().map(|frame| cells.resolve(frame)).collect();
+        }
+
+        Animation {
+            current: if clips.is_empty() { None } else { Some(0) },
+            clips: Rc::new(clips),
+            tick: 0,
+            finished: false,
+            held: false,
+        }
+    }
+
+    // Switches to the named clip and starts it from the beginning,
+    // even if it is the one already playing.
+    pub fn play(&mut self, name: &str) {
+        self.current = self.clips.iter().position(|clip| clip.name == name);
+        self.tick = 0;
+        self.finished = false;
+        self.held = false;
+    }
+
+    pub fn update(&mut self) -> Option<AnimationEvent> {
+        let held = std::mem::take(&mut self.held);
+        let clip = self.clip()?;
+        if self.finished {
+            return None;
+        }
+
+        let duration = clip.duration();
+        let playback = clip.playback;
+        self.tick += 1;
+        if self.tick < duration {
+            return None;
+        }
+
+        match playback {
+            Playback::Loop => {
+                self.tick = 0;
+                Some(AnimationEvent::Looped)
+            }
+            Playback::Once if held => {
+                self.tick = duration.saturating_sub(1);
+                None
+            }
+            Playback::Once => {
+                self.tick = duration.saturating_sub(1);
+                self.finished = true;
+                Some(AnimationEvent::Finished)
+            }
+        }
+    }//^-- fn update
+
+    // Keeps a Once clip on its last frame through the next update
+    // instead of finishing, so calling it every tick holds it there.
+    pub fn hold(&mut self) {
+        self.held = true;
+    }
+
+    pub fn clip(&self) -> Option<&Clip> {
+        self.current.and_then(|index| self.clips.get(index))
+    }
+
+    pub fn frame_name(&self) -> Option<&str> {
+        self.clip().and_then(|clip| {
+            clip.frames
+                .get((self.tick / clip.ticks_per_frame) as usize)
+                .map(String::as_str)
+        })
+    }
+
+    // How far through the current clip, from just above 0 to 1 on the last tick.
+    pub fn progress(&self) -> f32 {
+        self.clip()
+            .map(|clip| (self.tick + 1) as f32 / clip.duration().max(1) as f32)
+            .unwrap_or(0.0)
+    }
+
+    // The current frame's cell, or None if the sheet doesn't have it.
+    pub fn cell(&self) -> Option<CellId> {
+        self.clip().and_then(|clip| {
+            clip.cells
+                .get((self.tick / clip.ticks_per_frame) as usize)
+                .copied()
+                .flatten()
+        })
+    }
+}//^-- impl Animation
+
+//------------------- PARTICLES -------------------------
+
+// What one kind of particle looks like and how it moves.
+#[derive(Clone)]
+pub struct ParticleEffect {
+    // Each particle starts with a random velocity between these, per axis.
+    pub min_velocity: (f32, f32),
+    pub max_velocity: (f32, f32),
+    pub gravity: f32,
+    // In ticks.
+    pub lifetime: u16,
+    pub size: i16,
+    pub color: &'static str,
+    // Fades from opaque to transparent over the lifetime.
+    pub fade: bool,
+    // Drawn instead of a square of `color` when set.
+    pub sprite: Option<(Rc<SpriteSheet>, CellId)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EffectId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmitterId(usize);
+
+#[derive(Clone, Copy, Default)]
+struct Particle {
+    x: f32,
+    y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    age: u16,
+    lifetime: u16,
+    effect: usize,
+}
+
+impl Particle {
+    fn alive(&self) -> bool {
+        self.age < self.lifetime
+    }
+}
+
+// Emits `per_tick` particles every tick until stopped, a fraction carrying over.
+struct Emitter {
+    effect: EffectId,
+    position: Point,
+    per_tick: f32,
+    owed: f32,
+    active: bool,
+}
+
+/*
+A fixed pool of particles, so nothing is allocated after start up.
+When every particle is in use new ones are dropped rather than growing
+the pool. Positions are in world coordinates.
+*/
+pub struct ParticleSystem {
+    effects: Vec<ParticleEffect>,
+    particles: Vec<Particle>,
+    emitters: Vec<Emitter>,
+    // Where to start looking for a free particle.
+    next: usize,
+}
+
+impl ParticleSystem {
+    pub fn new(capacity: usize) -> Self {
+        ParticleSystem {
+            effects: vec![],
+            particles: vec![Particle::default(); capacity],
+            emitters: vec![],
+            next: 0,
+        }
+    }
+
+    pub fn add_effect(&mut self, effect: ParticleEffect) -> EffectId {
+        self.effects.push(effect);
+        EffectId(self.effects.len() - 1)
+    }
+
+    pub fn burst(&mut self, effect: EffectId, position: Point, count: u16) {
+        for _ in 0..count {
+            self.spawn(effect, position);
+        }
+    }
+
+    pub fn start(&mut self, effect: EffectId, position: Point, per_tick: f32) -> EmitterId {
+        let emitter = Emitter { effect, position, per_tick, owed: 0.0, active: true };
+        match self.emitters.iter().position(|emitter| !emitter.active) {
+            Some(index) => {
+                self.emitters[index] = emitter;
+                EmitterId(index)
+            }
+            None => {
+                self.emitters.push(emitter);
+                EmitterId(self.emitters.len() - 1)
+            }
+        }
+    }
+
+    pub fn move_emitter(&mut self, id: EmitterId, position: Point) {
+        if let Some(emitter) = self.emitters.get_mut(id.0) {
+            emitter.position = position;
+        }
+    }
+
+    // Stops emitting, the particles already out live out their lifetime.
+    pub fn stop(&mut self, id: EmitterId) {
+        if let Some(emitter) = self.emitters.get_mut(id.0) {
+            emitter.active = false;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.iter_mut().for_each(|particle| particle.lifetime = 0);
+        self.emitters.iter_mut().for_each(|emitter| emitter.active = false);
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.particles.iter().filter(|particle| particle.alive()).count()
+    }
+
+    pub fn move_horizontally(&mut self, x: i16) {
+        self.particles.iter_mut().for_each(|particle| particle.x += x as f32);
+        self.emitters.iter_mut().for_each(|emitter| emitter.position.x += x);
+    }
+
+    pub fn update(&mut self) {
+        for index in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[index];
+            if !emitter.active {
+                continue;
+            }
+            emitter.owed += emitter.per_tick;
+            let (effect, position) = (emitter.effect, emitter.position);
+            while self.emitters[index].owed >= 1.0 {
+                self.emitters[index].owed -= 1.0;
+                self.spawn(effect, position);
+            }
+        }
+
+        for particle in self.particles.iter_mut().filter(|particle| particle.alive()) {
+            particle.velocity_y += self.effects[particle.effect].gravity;
+            particle.x += particle.velocity_x;
+            particle.y += particle.velocity_y;
+            particle.age += 1;
+        }
+    }//^-- fn update
+
+    fn spawn(&mut self, effect: EffectId, position: Point) {
+        let capacity = self.particles.len();
+        let free = (0..capacity)
+                    .map(|offset| (self.next + offset) % capacity)
+                    .find(|&index| !self.particles[index].alive());
+        let (Some(index), Some(style)) = (free, self.effects.get(effect.0)) else {
+            return;
+        };
+
+        let mut rng = thread_rng();
+        let mut between = |min: f32, max: f32| if min < max { rng.gen_range(min..max) } else { min };
+        self.particles[index] = Particle {
+            x: position.x as f32,
+            y: position.y as f32,
+            velocity_x: between(style.min_velocity.0, style.max_velocity.0),
+            velocity_y: between(style.min_velocity.1, style.max_velocity.1),
+            age: 0,
+            lifetime: style.lifetime,
+            effect: effect.0,
+        };
+        self.next = (index + 1) % capacity;
+    }//^-- fn spawn
+
+    pub fn draw(&self, queue: &mut RenderQueue) {
+        for particle in self.particles.iter().filter(|particle| particle.alive()) {
+            let effect = &self.effects[particle.effect];
+            let alpha = if effect.fade {
+                1.0 - particle.age as f32 / particle.lifetime as f32
+            } else {
+                1.0
+            };
+            let centre = Point { x: particle.x as i16, y: particle.y as i16 };
+
+            match &effect.sprite {
+                Some((sheet, id)) => {
+                    let cell = sheet.cells().cell(*id);
+                    let (width, height) = (cell.frame.w, cell.frame.h);
+                    sheet.draw(
+                        queue,
+                        Layer::Particles,
+                        &Rect::new_from_x_y(cell.frame.x, cell.frame.y, width, height),
+                        &Rect::new_from_x_y(centre.x - width / 2, centre.y - height / 2, width, height),
+                        &DrawParams { alpha, ..Default::default() },
+                    );
+                }
+                None => queue.fill_rect_with_alpha(
+                    Layer::Particles,
+                    &Rect::new_from_x_y(
+                        centre.x - effect.size / 2,
+                        centre.y - effect.size / 2,
+                        effect.size,
+                        effect.size,
+                    ),
+                    effect.color,
+                    alpha,
+                ),
+            }
+        }
+    }//^-- fn draw
+}//^-- impl ParticleSystem
+
+//------------------- BROAD PHASE -------------------------
+
+/*
+A uniform grid for finding what's near a rect without checking
+everything. The world is a long strip, so the grid is one row of
+columns `column_width` wide, each listing the rects that reach into
+it. Rects are known by their index in the slice given to rebuild,
+which should happen whenever they move or come and go, not every tick.
+*/
+pub struct SpatialGrid {
+    column_width: i16,
+    first_column: i32,
+    columns: Vec<Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new(column_width: i16) -> Self {
+        SpatialGrid {
+            column_width: column_width.max(1),
+            first_column: 0,
+            columns: vec![],
+        }
+    }
+
+    fn column(&self, x: i16) -> i32 {
+        (x as i32).div_euclid(self.column_width as i32)
+    }
+
+    // Which columns a rect covers, as indexes into self.columns.
+    fn span(&self, rect: &Rect) -> std::ops::Range<usize> {
+        let last = self.first_column + self.columns.len() as i32;
+        let from = self.column(rect.x()).clamp(self.first_column, last);
+        let to = (self.column(rect.right() - 1) + 1).clamp(self.first_column, last);
+        (from - self.first_column) as usize..(to - self.first_column) as usize
+    }
+
+    pub fn rebuild(&mut self, rects: &[Rect]) {
+        self.columns.iter_mut().for_each(|column| column.clear());
+        if rects.is_empty() {
+            return;
+        }
+
+        let first = rects.iter().map(|rect| self.column(rect.x())).min().unwrap_or_default();
+        let last = rects.iter().map(|rect| self.column(rect.right() - 1)).max().unwrap_or_default();
+        self.first_column = first;
+        self.columns.resize_with((last - first + 1).max(1) as usize, Vec::new);
+
+        for (index, rect) in rects.iter().enumerate() {
+            for column in self.span(rect) {
+                self.columns[column].push(index);
+            }
+        }
+    }//^-- fn rebuild
+
+    /*
+    Everything in the columns `area` covers, in index order and each once.
+    That's a superset of what it touches, the caller still has to check.
+    */
+    pub fn query(&self, area: &Rect, found: &mut Vec<usize>) {
+        found.clear();
+        for column in self.span(area) {
+            found.extend_from_slice(&self.columns[column]);
+        }
+        found.sort_unstable();
+        found.dedup();
+    }
+}//^-- impl SpatialGrid
+
+//------------------- PATHS -------------------------
+
+/*
+How something that moves on its own gets about: its offset from where
+it was put, `tick` ticks in. Every path is a loop `period` ticks long
+that starts and ends at no offset.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Path {
+    #[default]
+    Still,
+    // Up and down, `amplitude` either side of where it was put.
+    Bob { amplitude: i16, period: u16 },
+    // Across `distance` to the right and back, easing at each end.
+    Patrol { distance: i16, period: u16 },
+}
+
+impl Path {
+    pub fn period(&self) -> u16 {
+        match self {
+            Path::Still => 1,
+            Path::Bob { period, .. } | Path::Patrol { period, .. } => (*period).max(1),
+        }
+    }
+
+    pub fn offset(&self, tick: u16) -> Point {
+        let turn = (tick % self.period()) as f32 / self.period() as f32 * std::f32::consts::TAU;
+        match self {
+            Path::Still => Point::default(),
+            Path::Bob { amplitude, .. } => Point {
+                x: 0,
+                y: (*amplitude as f32 * turn.sin()).round() as i16,
+            },
+            Path::Patrol { distance, .. } => Point {
+                x: (*distance as f32 * (1.0 - turn.cos()) / 2.0).round() as i16,
+                y: 0,
+            },
+        }
+    }
+
+    // The smallest and largest offsets it ever has.
+    pub fn extent(&self) -> (Point, Point) {
+        match self {
+            Path::Still => (Point::default(), Point::default()),
+            Path::Bob { amplitude, .. } => (
+                Point { x: 0, y: -amplitude.abs() },
+                Point { x: 0, y: amplitude.abs() },
+            ),
+            Path::Patrol { distance, .. } => (
+                Point { x: (*distance).min(0), y: 0 },
+                Point { x: (*distance).max(0), y: 0 },
+            ),
+        }
+    }
+}//^-- impl Path
+
+//------------------- TRANSITION TABLES -------------------------
+
+/*
+A state machine written out as data: from a state, on an event, every
+state it can end up in. Pairs the table leaves out leave the machine
+where it is. Tests hold a machine to its table, and to_dot draws it, so
+diagrams made from it can't drift from the code.
+*/
+pub struct TransitionTable {
+    pub name: &'static str,
+    pub initial: &'static str,
+    pub transitions: &'static [(&'static str, &'static str, &'static [&'static str])],
+}
+
+impl TransitionTable {
+    // None when the table leaves the pair out.
+    pub fn targets(&self, from: &str, event: &str) -> Option<&'static [&'static str]> {
+        self.transitions
+            .iter()
+            .find(|(state, on, _targets)| *state == from && *on == event)
+            .map(|(_state, _on, targets)| *targets)
+    }
+
+    // Every state named in the table, the initial one first.
+    pub fn states(&self) -> Vec<&'static str> {
+        let mut states = vec![self.initial];
+        for (from, _event, targets) in self.transitions {
+            for state in std::iter::once(from).chain(targets.iter()) {
+                if !states.contains(state) {
+                    states.push(state);
+                }
+            }
+        }
+        states
+    }
+
+    // Graphviz, e.g. `dot -Tsvg`; an edge per event, self loops included.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph {} {{\n", self.name);
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    node [shape=box, style=rounded];\n");
+        dot.push_str("    start [shape=point];\n");
+        for state in self.states() {
+            dot.push_str(&format!("    {};\n", state));
+        }
+        dot.push_str(&format!("    start -> {};\n", self.initial));
+        for (from, event, targets) in self.transitions {
+            for to in targets.iter() {
+                dot.push_str(&format!("    {} -> {} [label=\"{}\"];\n", from, to, event));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}//^-- impl TransitionTable
+
+//------------------- CAMERA -------------------------
+
+/*
+The window onto the world. Everything in the game lives in world
+coordinates and stays put; the camera moves, and the Renderer subtracts
+its position when drawing.
+*/
+#[derive(Clone, Copy, Default)]
+pub struct Camera {
+    position: Point,
+    width: i16,
+    height: i16,
+}
+
+impl Camera {
+    pub fn new(width: i16, height: i16) -> Self {
+        Camera {
+            position: Point::default(),
+            width,
+            height,
+        }
+    }
+
+    pub fn position(&self) -> Point {
+        self.position
+    }
+
+    // Keeps `target` at `screen_x` on screen, but never scrolls backwards.
+    pub fn follow(&mut self, target: Point, screen_x: i16) {
+        self.position.x = self.position.x.max(target.x - screen_x);
+    }
+
+    pub fn move_horizontally(&mut self, x: i16) {
+        self.position.x += x;
+    }
+
+    pub fn left(&self) -> i16 {
+        self.position.x
+    }
+
+    pub fn right(&self) -> i16 {
+        self.position.x + self.width
+    }
+
+    pub fn width(&self) -> i16 {
+        self.width
+    }
+
+    pub fn viewport(&self) -> Rect {
+        Rect::new(self.position, self.width, self.height)
+    }
+
+    pub fn to_screen(&self, point: Point) -> Point {
+        Point {
+            x: point.x - self.position.x,
+            y: point.y - self.position.y,
+        }
+    }
+}//^-- impl Camera
+
+//------------------- VIEWPORT -------------------------
+
+// The resolution the game is written for, whatever the canvas really is.
+pub const LOGICAL_WIDTH: i16 = 600;
+pub const LOGICAL_HEIGHT: i16 = 600;
+
+const LETTERBOX_COLOR: &str = "#000000";
+
+/*
+How the logical resolution fits on the canvas: the backing store is the
+canvas' CSS size times the device pixel ratio, so it's sharp on high-DPI
+screens, and the logical area is scaled to fit inside it, centred, with
+bars on whichever sides are left over.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub pixel_ratio: f64,
+    pub backing_width: u32,
+    pub backing_height: u32,
+    // Backing store pixels per logical unit.
+    pub scale: f64,
+    // Where the logical area starts, in backing store pixels.
+    pub offset_x: f64,
+    pub offset_y: f64,
+}
+
+impl Viewport {
+    pub fn fit(css_width: f64, css_height: f64, pixel_ratio: f64) -> Self {
+        let pixel_ratio = if pixel_ratio > 0.0 { pixel_ratio } else { 1.0 };
+        let backing_width = (css_width * pixel_ratio).round().max(1.0);
+        let backing_height = (css_height * pixel_ratio).round().max(1.0);
+        let scale = (backing_width / LOGICAL_WIDTH as f64)
+                        .min(backing_height / LOGICAL_HEIGHT as f64);
+
+        Viewport {
+            pixel_ratio,
+            backing_width: backing_width as u32,
+            backing_height: backing_height as u32,
+            scale,
+            offset_x: ((backing_width - LOGICAL_WIDTH as f64 * scale) / 2.0).floor(),
+            offset_y: ((backing_height - LOGICAL_HEIGHT as f64 * scale) / 2.0).floor(),
+        }
+    }
+
+    // Logical units to CSS pixels, for laying HTML over the canvas.
+    pub fn css_scale(&self) -> f64 {
+        self.scale / self.pixel_ratio
+    }
+}//^-- impl Viewport
+
+/*
+How draw_image should draw a sprite, all about the destination's centre.
+The default draws it as is.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawParams {
+    pub flip_x: bool,
+    // Radians, clockwise.
+    pub rotation: f32,
+    pub alpha: f32,
+    pub scale: f32,
+}
+
+impl Default for DrawParams {
+    fn default() -> Self {
+        DrawParams {
+            flip_x: false,
+            rotation: 0.0,
+            alpha: 1.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl DrawParams {
+    pub fn is_identity(&self) -> bool {
+        *self == DrawParams::default()
+    }
+
+    // The x and y scale to apply, with the flip folded into x.
+    fn scale_xy(&self) -> (f64, f64) {
+        let scale = self.scale as f64;
+        (if self.flip_x { -scale } else { scale }, scale)
+    }
+}
+
+// Ends a frame begun by Renderer::begin_frame when it's dropped.
+pub struct Frame<'a> {
+    context: &'a CanvasRenderingContext2d,
+}
+
+impl Drop for Frame<'_> {
+    fn drop(&mut self) {
+        self.context.restore();
+    }
+}
+
 pub struct Renderer {
     context: CanvasRenderingContext2d,
+    // Where the camera is while drawing the world, zero for screen space.
+    offset: std::cell::Cell<Point>,
+    viewport: std::cell::Cell<Option<Viewport>>,
 }
 
 impl Renderer {
+    fn new(context: CanvasRenderingContext2d) -> Self {
+        Renderer {
+            context,
+            offset: Default::default(),
+            viewport: Default::default(),
+        }
+    }
+
+    /*
+    Fits the canvas to the page, letterboxes it and sets the transform,
+    so everything drawn while the returned Frame lives is in logical units
+    and clipped to the logical area, which starts out clear. The Frame
+    restores the state saved here when dropped, including when a later
+    step fails, so the stack stays balanced however the frame ends.
+    */
+    pub fn begin_frame(&self) -> Result<Frame<'_>> {
+        let (css_width, css_height) = browser::canvas_css_size()?;
+        let viewport = Viewport::fit(css_width, css_height, browser::device_pixel_ratio()?);
+        if self.viewport.get() != Some(viewport) {
+            browser::resize_canvas(viewport.backing_width, viewport.backing_height)?;
+            browser::fit_ui(
+                viewport.offset_x / viewport.pixel_ratio,
+                viewport.offset_y / viewport.pixel_ratio,
+                LOGICAL_WIDTH.into(),
+                LOGICAL_HEIGHT.into(),
+                viewport.css_scale(),
+            )?;
+            self.viewport.set(Some(viewport));
+        }
+
+        self.context.save();
+        let frame = Frame { context: &self.context };
+        self.context
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .map_err(|err| anyhow!("Error resetting the transform {:#?}", err))?;
+        self.context.set_fill_style(&JsValue::from_str(LETTERBOX_COLOR));
+        self.context.fill_rect(
+            0.0,
+            0.0,
+            viewport.backing_width.into(),
+            viewport.backing_height.into(),
+        );
+        self.context
+            .set_transform(viewport.scale, 0.0, 0.0, viewport.scale, viewport.offset_x, viewport.offset_y)
+            .map_err(|err| anyhow!("Error setting the transform {:#?}", err))?;
+        self.context.begin_path();
+        self.context.rect(0.0, 0.0, LOGICAL_WIDTH.into(), LOGICAL_HEIGHT.into());
+        self.context.clip();
+        self.context.clear_rect(0.0, 0.0, LOGICAL_WIDTH.into(), LOGICAL_HEIGHT.into());
+
+        Ok(frame)
+    }//^-- fn begin_frame
+
+    /*
+    The transform and state stack. Every save needs a restore, with_state
+    pairs them up for you. Transforms apply on top of the camera, so a
+    translate is in screen space.
+    */
+    pub fn save(&self) {
+        self.context.save();
+    }
+
+    pub fn restore(&self) {
+        self.context.restore();
+    }
+
+    pub fn with_state<T>(&self, draw: impl FnOnce(&Renderer) -> T) -> T {
+        self.save();
+        let result = draw(self);
+        self.restore();
+        result
+    }
+
+    pub fn translate(&self, x: f64, y: f64) -> Result<()> {
+        self.context
+            .translate(x, y)
+            .map_err(|err| anyhow!("Error translating {:#?}", err))
+    }
+
+    pub fn scale(&self, x: f64, y: f64) -> Result<()> {
+        self.context
+            .scale(x, y)
+            .map_err(|err| anyhow!("Error scaling {:#?}", err))
+    }
+
+    pub fn rotate(&self, radians: f64) -> Result<()> {
+        self.context
+            .rotate(radians)
+            .map_err(|err| anyhow!("Error rotating {:#?}", err))
+    }
+
+    // Multiplies into the current alpha, so nested fades combine.
+    pub fn set_alpha(&self, alpha: f64) {
+        let current = self.context.global_alpha();
+        self.context.set_global_alpha(current * alpha.clamp(0.0, 1.0));
+    }
+
+    // Everything `draw` draws is in world coordinates, seen through `camera`.
+    pub fn with_camera<T>(&self, camera: &Camera, draw: impl FnOnce(&Renderer) -> T) -> T {
+        let previous = self.offset.replace(camera.position());
+        let result = draw(self);
+        self.offset.set(previous);
+        result
+    }
+
+    fn to_screen(&self, point: Point) -> Point {
+        let offset = self.offset.get();
+        Point {
+            x: point.x - offset.x,
+            y: point.y - offset.y,
+        }
+    }
+
+    // Clears in screen space, the camera doesn't apply.
     pub fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
             rect.x().into(), 
@@ -182,53 +1357,252 @@ impl Renderer {
     pub fn draw_image(&self, 
                         image: &HtmlImageElement, 
                         frame: &Rect, 
-                        destination: &Rect) {
+                        destination: &Rect,
+                        params: &DrawParams) -> Result<()> {
+        let position = self.to_screen(destination.position);
+        if params.is_identity() {
+            return self.blit(image, frame, position.x.into(), position.y.into(), destination);
+        }
+
+        // Draw about the origin, moved to the destination's centre.
+        let half_width = f64::from(destination.width) / 2.0;
+        let half_height = f64::from(destination.height) / 2.0;
+        let (scale_x, scale_y) = params.scale_xy();
+        self.with_state(|renderer| {
+            renderer.translate(f64::from(position.x) + half_width, f64::from(position.y) + half_height)?;
+            renderer.rotate(params.rotation.into())?;
+            renderer.scale(scale_x, scale_y)?;
+            renderer.set_alpha(params.alpha.into());
+            renderer.blit(image, frame, -half_width, -half_height, destination)
+        })
+    }//^-- draw_image
+
+    fn blit(&self, 
+            image: &HtmlImageElement, 
+            frame: &Rect, 
+            x: f64, 
+            y: f64, 
+            destination: &Rect) -> Result<()> {
         self.context
          .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-            &image,
+            image,
             frame.x().into(),
             frame.y().into(),
             frame.width.into(),
             frame.height.into(),
-            destination.x().into(),
-            destination.y().into(),
+            x,
+            y,
             destination.width.into(),
             destination.height.into(),
         )
-        .expect("Drawing is throwing exceptions! Unrecoverable error.");
-    }//^-- draw_image
+        .map_err(|err| anyhow!("Error drawing image {:#?}", err))
+    }//^-- fn blit
     
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) -> Result<()> {
+        let position = self.to_screen(*position);
         self.context
             .draw_image_with_html_image_element(image, 
                                                 position.x.into(), 
                                                 position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+            .map_err(|err| anyhow!("Error drawing image {:#?}", err))
     }//^-- draw_entire_image
     
+    pub fn fill_rect(&self, rect: &Rect, color: &str) {
+        let position = self.to_screen(rect.position);
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.fill_rect(
+            position.x.into(),
+            position.y.into(),
+            rect.width.into(),
+            rect.height.into(),
+        );
+    }
+
     //for debuging
-    #[allow(dead_code)]
     pub fn draw_rect(&self, bounding_box: &Rect) {
+        let position = self.to_screen(bounding_box.position);
         self.context.set_stroke_style(&JsValue::from_str("#FF0000"));
         self.context.begin_path();
         self.context.rect(
-            bounding_box.x().into(),
-            bounding_box.y().into(),
+            position.x.into(),
+            position.y.into(),
             bounding_box.width.into(),
             bounding_box.height.into(),
         );
         self.context.stroke();
     }
-    #[allow(dead_code)]
-    pub fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
-        self.context.set_font("16pt serif");
-        self.context
-            .fill_text(text, location.x.into(), location.y.into())
-            .map_err(|err| anyhow!("Error filling text {:#?}", err))?;
+    pub fn draw_text(&self, text: &str, location: &Point) -> Result<()> {
+        let location = self.to_screen(*location);
+        self.context.set_font("16pt serif");
+        self.context.set_fill_style(&JsValue::from_str("#000000"));
+        self.context
+            .fill_text(text, location.x.into(), location.y.into())
+            .map_err(|err| anyhow!("Error filling text {:#?}", err))?;
+
+        Ok(())
+    }
+}//^-- impl Renderer
+
+//------------------- RENDER QUEUE -------------------------
+
+// Back to front. Within a layer, things draw in the order they were submitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Background,
+    Obstacles,
+    Player,
+    Particles,
+    Hud,
+}
+
+/*
+Queued every frame, so nothing here calls into JS or allocates: images
+are shared through an Rc rather than cloning the element, and text is
+a range of the queue's text buffer.
+*/
+enum DrawCommand {
+    Image { image: Rc<HtmlImageElement>, frame: Rect, destination: Rect, params: DrawParams },
+    EntireImage { image: Rc<HtmlImageElement>, position: Point },
+    FillRect { rect: Rect, color: &'static str, alpha: f32 },
+    StrokeRect { rect: Rect },
+    Text { text: std::ops::Range<usize>, location: Point },
+}
+
+impl DrawCommand {
+    fn draw(&self, renderer: &Renderer, text_buffer: &str) -> Result<()> {
+        match self {
+            DrawCommand::Image { image, frame, destination, params } => 
+                renderer.draw_image(image, frame, destination, params),
+            DrawCommand::EntireImage { image, position } => 
+                renderer.draw_entire_image(image, position),
+            DrawCommand::FillRect { rect, color, alpha } if *alpha < 1.0 => {
+                renderer.with_state(|renderer| {
+                    renderer.set_alpha((*alpha).into());
+                    renderer.fill_rect(rect, color);
+                });
+                Ok(())
+            }
+            DrawCommand::FillRect { rect, color, .. } => {
+                renderer.fill_rect(rect, color);
+                Ok(())
+            }
+            DrawCommand::StrokeRect { rect } => {
+                renderer.draw_rect(rect);
+                Ok(())
+            }
+            DrawCommand::Text { text, location } => 
+                renderer.draw_text(&text_buffer[text.clone()], location),
+        }
+    }
+}
+
+struct QueuedCommand {
+    layer: Layer,
+    // The camera position when it was submitted.
+    offset: Point,
+    command: DrawCommand,
+}
+
+/*
+Everything the game draws in a frame, collected and then drawn by layer
+in flush, whatever order it was submitted in. The queue is kept between
+frames so it only allocates while it grows. This is also the place to
+batch draws, e.g. runs of images from the same sheet.
+*/
+#[derive(Default)]
+pub struct RenderQueue {
+    commands: Vec<QueuedCommand>,
+    // All the frame's text, end to end.
+    text: String,
+    offset: Point,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        RenderQueue::default()
+    }
 
-        Ok(())
+    // Everything `submit` queues is in world coordinates, seen through `camera`.
+    pub fn with_camera<T>(&mut self, camera: &Camera, submit: impl FnOnce(&mut RenderQueue) -> T) -> T {
+        let previous = std::mem::replace(&mut self.offset, camera.position());
+        let result = submit(self);
+        self.offset = previous;
+        result
     }
-}//^-- impl Renderer
+
+    fn push(&mut self, layer: Layer, command: DrawCommand) {
+        self.commands.push(QueuedCommand {
+            layer,
+            offset: self.offset,
+            command,
+        });
+    }
+
+    pub fn draw_image(&mut self, 
+                      layer: Layer, 
+                      image: &Rc<HtmlImageElement>, 
+                      frame: &Rect, 
+                      destination: &Rect, 
+                      params: &DrawParams) {
+        self.push(layer, DrawCommand::Image {
+            image: image.clone(),
+            frame: *frame,
+            destination: *destination,
+            params: *params,
+        });
+    }
+
+    pub fn draw_entire_image(&mut self, layer: Layer, image: &Rc<HtmlImageElement>, position: &Point) {
+        self.push(layer, DrawCommand::EntireImage { image: image.clone(), position: *position });
+    }
+
+    pub fn fill_rect(&mut self, layer: Layer, rect: &Rect, color: &'static str) {
+        self.fill_rect_with_alpha(layer, rect, color, 1.0);
+    }
+
+    pub fn fill_rect_with_alpha(&mut self, layer: Layer, rect: &Rect, color: &'static str, alpha: f32) {
+        self.push(layer, DrawCommand::FillRect { rect: *rect, color, alpha });
+    }
+
+    pub fn draw_rect(&mut self, layer: Layer, rect: &Rect) {
+        self.push(layer, DrawCommand::StrokeRect { rect: *rect });
+    }
+
+    // Takes anything Display, so format_args! can write straight into the buffer.
+    pub fn draw_text(&mut self, layer: Layer, text: impl std::fmt::Display, location: &Point) {
+        use std::fmt::Write;
+        let start = self.text.len();
+        if write!(self.text, "{}", text).is_err() {
+            self.text.truncate(start);
+            return;
+        }
+        self.push(layer, DrawCommand::Text { text: start..self.text.len(), location: *location });
+    }
+
+    // Stable, so submission order holds within a layer.
+    fn sort(&mut self) {
+        self.commands.sort_by_key(|queued| queued.layer);
+    }
+
+    pub fn flush(&mut self, renderer: &Renderer) -> Result<()> {
+        self.sort();
+        let text = &self.text;
+        let drawn = collect_errors(self.commands.drain(..).map(|queued| {
+            renderer.offset.set(queued.offset);
+            queued.command.draw(renderer, text)
+        }));
+        self.text.clear();
+        renderer.offset.set(Point::default());
+        drawn
+    }
+}//^-- impl RenderQueue
+
+fn canvas_context() -> Result<CanvasRenderingContext2d> {
+    browser::context().map_err(|err| {
+        error!("{:#?}", err);
+        EngineError::CanvasMissing.into()
+    })
+}
 
 pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
     let image = browser::new_image()?;
@@ -266,8 +1640,8 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
 #[async_trait(?Send)]
 pub trait Game {
     async fn initialize(&self) -> Result<Box<dyn Game>>;
-    fn update(&mut self, keystate: &KeyState);
-    fn draw(&self, context: &Renderer);
+    fn update(&mut self, keystate: &KeyState) -> Result<()>;
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()>;
 }
 
 const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
@@ -280,7 +1654,7 @@ pub struct GameLoop {
 type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
 
 
-unsafe fn draw_frame_rate(renderer: &Renderer, frame_time: f64)
+unsafe fn draw_frame_rate(queue: &mut RenderQueue, frame_time: f64)
 {
     static mut FRAMES_COUNTED: i32 = 0;
     static mut TOTAL_FRAME_TIME: f64 = 0.0;
@@ -295,12 +1669,11 @@ unsafe fn draw_frame_rate(renderer: &Renderer, frame_time: f64)
         FRAMES_COUNTED = 0;
     }
 
-    if let Err(err) = renderer.draw_text(
-        &format!("Frame Rate {}", FRAME_RATE),
+    queue.draw_text(
+        Layer::Hud,
+        format_args!("Frame Rate {}", FRAME_RATE),
         &Point { x: 400, y: 100 },
-    ) {
-        error!("Could not draw text {:#?}", err);
-      }
+    );
 }//^-- unsafe fn draw_frame_rate
 
 
@@ -316,9 +1689,8 @@ impl GameLoop {
             accumulated_delta: 0.0,
         };
 
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
+        let renderer = Renderer::new(canvas_context()?);
+        let mut queue = RenderQueue::new();
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
@@ -328,22 +1700,41 @@ impl GameLoop {
             process_input(&mut keystate, &mut keyevent_receiver);
 
             //game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32;
+            let mut frame_errors = vec![];
             let frame_time = perf - game_loop.last_frame;
             game_loop.accumulated_delta += frame_time as f32;
             while game_loop.accumulated_delta > FRAME_SIZE {
-                game.update(&keystate);
+                if let Err(err) = game.update(&keystate) {
+                    frame_errors.push(err);
+                }
+                keystate.end_update();
                 game_loop.accumulated_delta -= FRAME_SIZE;
             }
             game_loop.last_frame = perf;
             //game.draw(&browser::context().expect("Context should exist",));
-            game.draw(&renderer);
-
+            let frame = renderer.begin_frame().map_err(|err| frame_errors.push(err)).ok();
+            if let Err(err) = game.draw(&mut queue) {
+                frame_errors.push(err);
+            }
             if cfg!(debug_assertions) {
                 unsafe {
-                    draw_frame_rate(&renderer, frame_time);
+                    draw_frame_rate(&mut queue, frame_time);
+                }
+            }
+            if let Err(err) = queue.flush(&renderer) {
+                frame_errors.push(err);
+            }
+            drop(frame);
+
+            // One report per frame, however many things went wrong.
+            if let Err(err) = collect_errors(frame_errors.into_iter().map(Err)) {
+                error!("{:#}", err);
+            }
+            if let Some(closure) = f.borrow().as_ref() {
+                if let Err(err) = browser::request_animation_frame(closure) {
+                    error!("{:#?}", err);
                 }
             }
-            let _ = browser::request_animation_frame(f.borrow().as_ref().unwrap());
         }));
 
         browser::request_animation_frame(
@@ -357,6 +1748,25 @@ impl GameLoop {
 
 }//-- GameLoop
 
+/*
+Runs every result to completion rather than stopping at the first error,
+so one broken sprite doesn't keep the rest of the frame from drawing.
+All the errors are folded into a single one.
+*/
+pub fn collect_errors(results: impl IntoIterator<Item = Result<()>>) -> Result<()> {
+    let errors: Vec<String> = results
+                                .into_iter()
+                                .filter_map(|result| result.err())
+                                .map(|err| format!("{:#}", err))
+                                .collect();
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("{}", errors[0])),
+        count => Err(anyhow!("{} errors: {}", count, errors.join("; "))),
+    }
+}
+
 // =============== 
 // Keyboard input
 
@@ -369,12 +1779,15 @@ enum KeyPress {
 #[derive(Debug)]
 pub struct KeyState {
     pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+    // Went down since the last update, for toggles that shouldn't repeat.
+    just_pressed: Vec<String>,
 }
 
 impl KeyState {
     fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            just_pressed: vec![],
         }
     }
 
@@ -382,12 +1795,23 @@ impl KeyState {
         self.pressed_keys.contains_key(code)
     }
 
+    pub fn was_pressed(&self, code: &str) -> bool {
+        self.just_pressed.iter().any(|pressed| pressed == code)
+    }
+
     fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
+        if !self.is_pressed(code) {
+            self.just_pressed.push(code.into());
+        }
         self.pressed_keys.insert(code.into(), event);
     }
 
+    fn end_update(&mut self) {
+        self.just_pressed.clear();
+    }
+
     fn set_released(&mut self, code: &str) {
-        self.pressed_keys.remove(code.into());
+        self.pressed_keys.remove(code);
     }
 
 }//^-- impl KeyState
@@ -435,9 +1859,10 @@ fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver
 
 //------------------- SOUND -------------------------
 
+// Without Web Audio there's no context, and every sound is silence.
 #[derive(Clone)]
 pub struct Audio {
-    context: AudioContext,
+    context: Option<AudioContext>,
 }
 
 #[derive(Clone)]
@@ -447,24 +1872,46 @@ pub struct Sound {
 
 
 impl Audio {
-    pub fn new() -> Result<Self> {
-        Ok(Audio { context: sound::create_audio_context()?,})
+    // Opens the browser's audio, which is more than a Default should do.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let context = sound::create_audio_context()
+                        .map_err(|err| { error!("{} {:#?}", EngineError::AudioUnavailable, err); })
+                        .ok();
+
+        Audio { context }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.context.is_some()
     }
 
-    pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
-        let array_buffer = browser::fetch_array_buffer(filename).await?;
-        let audio_buffer = sound::decode_audio_data(&self.context, &array_buffer).await?;
+    pub async fn decode_sound(&self, array_buffer: &ArrayBuffer) -> Result<Sound> {
+        let context = self.context.as_ref().ok_or(EngineError::AudioUnavailable)?;
+        let audio_buffer = sound::decode_audio_data(context, array_buffer).await?;
         
         Ok(Sound { buffer: audio_buffer,})
     }
 
     pub fn play_sound(&self, sound: &Sound) -> Result<()> {
         //sound::play_sound(&self.context, &sound.buffer)
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::NO)
+        match &self.context {
+            Some(context) => sound::play_sound(context, &sound.buffer, sound::LOOPING::NO),
+            None => Ok(()),
+        }
     }  
+    pub fn play_sound_at_rate(&self, sound: &Sound, rate: f32) -> Result<()> {
+        match &self.context {
+            Some(context) => sound::play_sound_at_rate(context, &sound.buffer, rate),
+            None => Ok(()),
+        }
+    }
     #[allow(dead_code)]
     pub fn play_looping_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::YES)
+        match &self.context {
+            Some(context) => sound::play_sound(context, &sound.buffer, sound::LOOPING::YES),
+            None => Ok(()),
+        }
     }
 
 }
@@ -485,12 +1932,341 @@ pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
     click_receiver
 }
 
+/*
+Shown instead of the game when it can't start. Errors the engine knows
+about say what failed, anything else gets a generic message.
+The returned receiver fires when the player presses Retry.
+*/
+pub fn draw_error_screen(err: &anyhow::Error) -> Result<UnboundedReceiver<()>> {
+    let message = match err.downcast_ref::<EngineError>() {
+        Some(engine_error) => engine_error.to_string(),
+        None => "Something went wrong starting the game".to_string(),
+    };
+
+    browser::draw_ui(&format!(
+            "<div id='error'><p>{}</p><button id='retry'>Retry</button></div>",
+            escape_html(&message)))
+        .and_then(|_unit| browser::find_html_element_by_id("retry"))
+        .map(add_click_handler)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&#39;")
+}
+
+//------------------- ASSETS -------------------------
+
+/*
+The manifest lists every asset the game needs by key, so the game code
+never hard-codes a path. It lives next to the other resources and looks like:
+
+{
+  "images": { "stone": "../resources/pix/Stone.png" },
+  "sheets": { "rhb": { "json": "../resources/pix/rhb.json",
+                       "image": "../resources/pix/rhb.png" } },
+  "sounds": { "jump": "../resources/sound/SFX_Jump_23.mp3" },
+  "optional": ["jump"],
+  "hitboxes": { "stone": [{"name": "body", "x": 4, "y": 8, "w": 82, "h": 46}] }
+}
+
+Keys listed in "optional" may fail to load without stopping the game;
+they are simply missing from the loaded Assets. "hitboxes" gives plain
+images the hitboxes sheet cells carry themselves.
+*/
+#[derive(Deserialize, Clone, Default)]
+pub struct AssetManifest {
+    #[serde(default)]
+    pub images: HashMap<String, String>,
+    #[serde(default)]
+    pub sheets: HashMap<String, SheetSource>,
+    #[serde(default)]
+    pub sounds: HashMap<String, String>,
+    #[serde(default)]
+    pub optional: Vec<String>,
+    #[serde(default)]
+    pub hitboxes: HashMap<String, Vec<Hitbox>>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SheetSource {
+    pub json: String,
+    pub image: String,
+}
+
+impl AssetManifest {
+    pub async fn fetch(source: &str) -> Result<AssetManifest> {
+        const KEY: &str = "manifest";
+
+        let json = browser::fetch_json(source)
+                        .await
+                        .map_err(|err| missing_asset(KEY, source, err))?;
+        JsValueSerdeExt::into_serde(&json)
+            .map_err(|err| decode_failure(KEY, source, err.into()).into())
+    }
+
+    pub fn is_optional(&self, key: &str) -> bool {
+        self.optional.iter().any(|optional| optional == key)
+    }
+
+    // An image's hitboxes, if the manifest gives it any.
+    pub fn hitboxes(&self, key: &str) -> &[Hitbox] {
+        self.hitboxes.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len() + self.sheets.len() + self.sounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}//^-- impl AssetManifest
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadingProgress {
+    pub loaded: usize,
+    pub total: usize,
+}
+
+impl LoadingProgress {
+    // Nothing loaded yet out of what load_assets will fetch, which leaves
+    // out the sounds when there's no audio to decode them with.
+    pub fn start(manifest: &AssetManifest, with_sounds: bool) -> Self {
+        let sounds = if with_sounds { manifest.sounds.len() } else { 0 };
+        LoadingProgress {
+            loaded: 0,
+            total: manifest.images.len() + manifest.sheets.len() + sounds,
+        }
+    }
+
+    // Fraction of the assets loaded so far, from 0.0 to 1.0.
+    // An empty manifest counts as fully loaded.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.loaded as f32 / self.total as f32
+        }
+    }
+}
+
+// Everything listed in the manifest, loaded and ready to use.
+pub struct Assets {
+    images: HashMap<String, HtmlImageElement>,
+    sheets: HashMap<String, Rc<SpriteSheet>>,
+    sounds: HashMap<String, Sound>,
+}
+
+impl Assets {
+    pub fn image(&self, key: &str) -> Result<HtmlImageElement> {
+        self.images
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow!("Image asset '{}' not found", key))
+    }
+
+    pub fn sprite_sheet(&self, key: &str) -> Result<Rc<SpriteSheet>> {
+        self.sheets
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow!("Sprite sheet asset '{}' not found", key))
+    }
+
+    // For assets the manifest marks as optional, which may not have loaded,
+    // and for every sound when there's no audio to load them with.
+    pub fn optional_sound(&self, key: &str) -> Option<Sound> {
+        self.sounds.get(key).cloned()
+    }
+}//^-- impl Assets
+
+/*
+All the assets are requested at once and awaited with join_all, so the
+total load time is the slowest asset rather than the sum of all of them.
+`on_progress` is called once before anything loads and again every time
+an asset finishes. Without audio the sounds aren't fetched at all.
+Every asset that fails is reported, not just the first.
+*/
+pub async fn load_assets(manifest: &AssetManifest,
+                         audio: &Audio,
+                         on_progress: impl FnMut(&LoadingProgress)) -> Result<Assets> {
+
+    let with_sounds = audio.is_available();
+    let progress = RefCell::new(LoadingProgress::start(manifest, with_sounds));
+    let on_progress = RefCell::new(on_progress);
+    (on_progress.borrow_mut())(&progress.borrow());
+
+    let tick = || {
+        let mut progress = progress.borrow_mut();
+        progress.loaded += 1;
+        (on_progress.borrow_mut())(&progress);
+    };
+    let tick = &tick;
+
+    let images = future::join_all(manifest.images.iter().map(|(key, source)| async move {
+        let image = load_image(source)
+                        .await
+                        .map_err(|err| missing_asset(key, source, err));
+        tick();
+        settle(manifest, key, image)
+    }));
+
+    let sheets = future::join_all(manifest.sheets.iter().map(|(key, source)| async move {
+        let sheet = async {
+            let (json, image) = future::try_join(
+                    browser::fetch_json(&source.json)
+                        .map_err(|err| missing_asset(key, &source.json, err)),
+                    load_image(&source.image)
+                        .map_err(|err| missing_asset(key, &source.image, err)),
+                ).await?;
+            let sheet: Sheet = JsValueSerdeExt::into_serde(&json)
+                        .map_err(|err| decode_failure(key, &source.json, err.into()))?;
+
+            SpriteSheet::new(sheet, image)
+                .map(Rc::new)
+                .map_err(|err| decode_failure(key, &source.json, err))
+        };
+        let sheet = sheet.await;
+        tick();
+        settle(manifest, key, sheet)
+    }));
+
+    let sounds = manifest.sounds.iter().filter(|_sound| with_sounds);
+    let sounds = future::join_all(sounds.map(|(key, source)| async move {
+        let sound = async {
+            let array_buffer = browser::fetch_array_buffer(source)
+                        .await
+                        .map_err(|err| missing_asset(key, source, err))?;
+            audio.decode_sound(&array_buffer)
+                        .await
+                        .map_err(|err| decode_failure(key, source, err))
+        };
+        let sound = sound.await;
+        tick();
+        settle(manifest, key, sound)
+    }));
+
+    let (images, sheets, sounds) = futures::join!(images, sheets, sounds);
+
+    let mut failures = vec![];
+    let assets = Assets {
+        images: loaded(images, &mut failures),
+        sheets: loaded(sheets, &mut failures),
+        sounds: loaded(sounds, &mut failures),
+    };
+
+    match failures.len() {
+        0 => Ok(assets),
+        1 => Err(failures.remove(0).into()),
+        _ => Err(EngineError::AssetsFailed(failures).into()),
+    }
+}//^-- async fn load_assets
+
+// Optional assets that failed are logged and left out.
+fn settle<T>(manifest: &AssetManifest, 
+             key: &str, 
+             result: Result<T, EngineError>) -> Result<Option<(String, T)>, EngineError> {
+    match result {
+        Ok(asset) => Ok(Some((key.to_string(), asset))),
+        Err(err) if manifest.is_optional(key) => {
+            log!("Skipping optional asset: {}", err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// The assets that loaded; the failures go on the end of `failures`.
+fn loaded<T>(results: Vec<Result<Option<(String, T)>, EngineError>>, 
+             failures: &mut Vec<EngineError>) -> HashMap<String, T> {
+    let mut assets = HashMap::new();
+    for result in results {
+        match result {
+            Ok(Some((key, asset))) => { assets.insert(key, asset); }
+            Ok(None) => (),
+            Err(err) => failures.push(err),
+        }
+    }
+
+    assets
+}
+
+fn missing_asset(key: &str, source: &str, err: anyhow::Error) -> EngineError {
+    error!("{:#?}", err);
+    EngineError::MissingAsset { key: key.to_string(), source: source.to_string() }
+}
+
+fn decode_failure(key: &str, source: &str, err: anyhow::Error) -> EngineError {
+    error!("{:#?}", err);
+    EngineError::DecodeFailure { key: key.to_string(), source: source.to_string() }
+}
+
+// Draws a progress bar straight onto the canvas while the game loop
+// isn't running yet.
+pub struct LoadingScreen {
+    renderer: Renderer,
+}
+
+impl LoadingScreen {
+    const BAR: Rect = Rect::new_from_x_y(100, 285, 400, 30);
+
+    pub fn new() -> Result<Self> {
+        Ok(LoadingScreen {
+            renderer: Renderer::new(canvas_context()?),
+        })
+    }
+
+    pub fn draw(&self, progress: &LoadingProgress) {
+        let bar = &LoadingScreen::BAR;
+        let filled = (bar.width as f32 * progress.fraction()) as i16;
+
+        let frame = self.renderer.begin_frame().map_err(|err| { error!("Could not fit the canvas {:#?}", err); }).ok();
+        self.renderer.fill_rect(&Rect::new(bar.position, filled, bar.height), "#C0392B");
+        self.renderer.draw_rect(bar);
+        if let Err(err) = self.renderer.draw_text(
+            &format!("Loading {}/{}", progress.loaded, progress.total),
+            &Point { x: bar.x(), y: bar.y() - 10 },
+        ) {
+            error!("Could not draw loading text {:#?}", err);
+        }
+        drop(frame);
+    }
+}//^-- impl LoadingScreen
+
 //======================================
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    #[test]
+    fn transition_tables_list_their_states_and_draw_every_edge() {
+        let table = TransitionTable {
+            name: "Door",
+            initial: "Shut",
+            transitions: &[
+                ("Shut", "Push", &["Open", "Locked"]),
+                ("Open", "Push", &["Shut"]),
+            ],
+        };
+
+        assert_eq!(table.states(), ["Shut", "Open", "Locked"]);
+        assert_eq!(table.targets("Open", "Push"), Some(&["Shut"][..]));
+        assert_eq!(table.targets("Locked", "Push"), None);
+        assert_eq!(table.to_dot(), "digraph Door {\n    \
+                                    rankdir=LR;\n    \
+                                    node [shape=box, style=rounded];\n    \
+                                    start [shape=point];\n    \
+                                    Shut;\n    Open;\n    Locked;\n    \
+                                    start -> Shut;\n    \
+                                    Shut -> Open [label=\"Push\"];\n    \
+                                    Shut -> Locked [label=\"Push\"];\n    \
+                                    Open -> Shut [label=\"Push\"];\n\
+                                    }\n");
+    }
+
     #[test]
     fn two_rects_that_intersect_on_the_left() {
         let rect1 = Rect {
@@ -504,7 +2280,405 @@ mod tests {
             width: 100,
         };
 
-        assert_eq!(rect2.intersects(&rect1), true);
+        assert!(rect2.intersects(&rect1));
+    }
+
+    #[test]
+    fn penetration_finds_the_shallow_side() {
+        let platform = Rect::new_from_x_y(100, 400, 200, 90);
+
+        let landing = Rect::new_from_x_y(150, 300, 60, 104);
+        let (depth, side) = landing.penetration(&platform).unwrap();
+        assert_eq!((depth.x, depth.y, side), (60, 4, Side::Top));
+
+        let running_into = Rect::new_from_x_y(95, 380, 60, 100);
+        assert_eq!(running_into.penetration(&platform).unwrap().1, Side::Left);
+
+        let from_below = Rect::new_from_x_y(150, 480, 60, 100);
+        assert_eq!(from_below.penetration(&platform).unwrap().1, Side::Bottom);
+
+        assert!(Rect::new_from_x_y(300, 400, 10, 10).penetration(&platform).is_none());
+    }
+
+    // Just short of ORIGIN_LIMIT, where doubling a centre doesn't fit in an i16.
+    #[test]
+    fn sides_are_found_right_up_to_the_origin_shift() {
+        let platform = Rect::new_from_x_y(16300, 400, 200, 90);
+
+        let landing = Rect::new_from_x_y(16350, 300, 60, 104);
+        assert_eq!(landing.penetration(&platform), Some((Point { x: 60, y: 4 }, Side::Top)));
+        assert_eq!(landing.sweep(Point { x: 3, y: 4 }, &platform).unwrap().normal, Side::Top);
+
+        let past_the_edge = Rect::new_from_x_y(16360, 380, 57, 107);
+        let edge = Rect::new_from_x_y(16340, 420, 68, 93);
+        assert_eq!(past_the_edge.penetration(&edge).unwrap().1, Side::Right);
+    }
+
+    #[test]
+    fn sweep_catches_what_intersects_tunnels_through() {
+        let edge = Rect::new_from_x_y(100, 400, 60, 10);
+        let falling = Rect::new_from_x_y(110, 370, 30, 20);
+        let velocity = Point { x: 2, y: 40 };
+
+        let after = Rect::new_from_x_y(112, 410, 30, 20);
+        assert!(!after.intersects(&edge));
+
+        let sweep = falling.sweep(velocity, &edge).unwrap();
+        assert_eq!(sweep.normal, Side::Top);
+        assert_eq!(sweep.time, 0.25);
+
+        let running = Rect::new_from_x_y(70, 395, 28, 20);
+        assert_eq!(running.sweep(Point { x: 3, y: 0 }, &edge).unwrap().normal, Side::Left);
+
+        assert!(falling.sweep(Point { x: -50, y: 0 }, &edge).is_none());
+
+        let walking_off = Rect::new_from_x_y(159, 388, 30, 14);
+        assert_eq!(walking_off.penetration(&edge).unwrap().1, Side::Right);
+        assert_eq!(walking_off.sweep(Point { x: 3, y: 1 }, &edge).unwrap().normal, Side::Top);
+    }
+
+    #[test]
+    fn grid_finds_the_same_rects_as_a_linear_scan() {
+        let rects: Vec<Rect> = (0..40)
+            .map(|i| Rect::new_from_x_y(i * 150 - 900, 300 + (i % 3) * 50, 90 + (i % 4) * 100, 60))
+            .collect();
+        let mut grid = SpatialGrid::new(256);
+        grid.rebuild(&rects);
+
+        let mut found = vec![];
+        for x in (-1200..6000).step_by(37) {
+            let area = Rect::new_from_x_y(x, 280, 60, 130);
+            grid.query(&area, &mut found);
+            let touching: Vec<usize> = found.iter().copied().filter(|&i| rects[i].intersects(&area)).collect();
+            let scanned: Vec<usize> = (0..rects.len()).filter(|&i| rects[i].intersects(&area)).collect();
+            assert_eq!(touching, scanned);
+        }
+
+        grid.rebuild(&[]);
+        grid.query(&Rect::new_from_x_y(0, 0, 600, 600), &mut found);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn paths_loop_within_their_extent() {
+        let bob = Path::Bob { amplitude: 40, period: 120 };
+        assert_eq!(bob.offset(0).y, 0);
+        assert_eq!(bob.offset(30).y, 40);
+        assert_eq!(bob.offset(90).y, -40);
+        assert_eq!(bob.offset(120).y, 0);
+
+        let patrol = Path::Patrol { distance: 150, period: 240 };
+        assert_eq!(patrol.offset(120).x, 150);
+
+        for path in [bob, patrol, Path::Still] {
+            let (low, high) = path.extent();
+            for tick in 0..path.period() {
+                let offset = path.offset(tick);
+                assert!(low.x <= offset.x && offset.x <= high.x);
+                assert!(low.y <= offset.y && offset.y <= high.y);
+            }
+        }
+    }
+
+    #[test]
+    fn error_messages_name_the_failing_asset() {
+        let err = EngineError::MissingAsset {
+            key: "jump".to_string(),
+            source: "../resources/sound/SFX_Jump_23.mp3".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Could not load asset 'jump' from ../resources/sound/SFX_Jump_23.mp3"
+        );
+        assert_eq!(escape_html("<b>'a' & b</b>"), "&lt;b&gt;&#39;a&#39; &amp; b&lt;/b&gt;");
+    }
+
+    #[test]
+    fn every_failed_asset_is_reported() {
+        let missing = |key: &str| EngineError::MissingAsset { key: key.to_string(), source: format!("{}.png", key) };
+        let mut failures = vec![];
+
+        let images = loaded(vec![Err(missing("stone")), Ok(Some(("bg".to_string(), 1))), Ok(None), Err(missing("coin"))], 
+                            &mut failures);
+
+        assert_eq!(images, HashMap::from([("bg".to_string(), 1)]));
+        assert_eq!(
+            EngineError::AssetsFailed(failures).to_string(),
+            "2 assets failed: Could not load asset 'stone' from stone.png; Could not load asset 'coin' from coin.png"
+        );
+    }
+
+    #[test]
+    fn collect_errors_reports_every_failure_at_once() {
+        assert!(collect_errors(vec![Ok(()), Ok(())]).is_ok());
+
+        let err = collect_errors(vec![
+            Err(anyhow!("Cell not found: Run (7).png")),
+            Ok(()),
+            Err(anyhow!("Error drawing image")),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2 errors: Cell not found: Run (7).png; Error drawing image"
+        );
+    }
+
+    #[test]
+    fn sheet_report_lists_missing_cells() {
+        let cell = Cell {
+            frame: SheetRect { x: 0, y: 0, w: 10, h: 10 },
+            sprite_source_size: SheetRect { x: 0, y: 0, w: 10, h: 10 },
+            hitboxes: vec![],
+        };
+        let sheet = Sheet {
+            frames: HashMap::from([("Run (1).png".to_string(), cell)]),
+        };
+
+        let report = SheetReport::check("rhb", &sheet, ["Run (1).png", "Run (2).png"]);
+        assert!(!report.is_ok());
+        assert_eq!(report.to_string(), "rhb: 1 missing cell(s): Run (2).png");
+        assert!(SheetReport::check("rhb", &sheet, ["Run (1).png"]).is_ok());
+    }
+
+    #[test]
+    fn looping_clips_wrap_and_one_shot_clips_hold_their_last_frame() {
+        let mut animation = Animation::new(vec![
+            Clip::numbered("Run", 2, 2, Playback::Loop),
+            Clip::numbered("Slide", 2, 1, Playback::Once),
+        ], &CellTable::new(&Sheet { frames: HashMap::new() }).unwrap());
+
+        let mut frames = vec![];
+        let mut events = vec![];
+        for _ in 0..4 {
+            frames.push(animation.frame_name().unwrap().to_string());
+            events.push(animation.update());
+        }
+        assert_eq!(frames, ["Run (1).png", "Run (1).png", "Run (2).png", "Run (2).png"]);
+        assert_eq!(events, [None, None, None, Some(AnimationEvent::Looped)]);
+        assert_eq!(animation.frame_name(), Some("Run (1).png"));
+
+        animation.play("Slide");
+        assert_eq!(animation.update(), None);
+        for _ in 0..3 {
+            animation.hold();
+            assert_eq!(animation.update(), None);
+            assert_eq!(animation.frame_name(), Some("Slide (2).png"));
+        }
+        assert_eq!(animation.update(), Some(AnimationEvent::Finished));
+        assert_eq!(animation.update(), None);
+        assert_eq!(animation.frame_name(), Some("Slide (2).png"));
+        assert_eq!(animation.progress(), 1.0);
+
+        animation.play("Missing");
+        assert_eq!(animation.frame_name(), None);
+        assert_eq!(animation.update(), None);
+    }
+
+    #[test]
+    fn animation_frames_resolve_to_cells_in_the_table() {
+        let cell = |x| Cell {
+            frame: SheetRect { x, y: 0, w: 10, h: 10 },
+            sprite_source_size: SheetRect { x: 0, y: 0, w: 10, h: 10 },
+            hitboxes: vec![],
+        };
+        let sheet = Sheet {
+            frames: HashMap::from([
+                ("Run (1).png".to_string(), cell(0)),
+                ("Run (2).png".to_string(), cell(10)),
+            ]),
+        };
+        let cells = CellTable::new(&sheet).unwrap();
+        let mut animation = Animation::new(vec![Clip::numbered("Run", 3, 1, Playback::Loop)], &cells);
+
+        let x = |animation: &Animation| animation.cell().map(|id| cells.cell(id).frame.x);
+        assert_eq!(x(&animation), Some(0));
+        animation.update();
+        assert_eq!(x(&animation), Some(10));
+        animation.update();
+        assert_eq!(x(&animation), None);
+        assert_eq!(animation.frame_name(), Some("Run (3).png"));
+    }
+
+    #[test]
+    fn sheets_with_more_frames_than_cell_ids_are_refused() {
+        let cell = Cell {
+            frame: SheetRect { x: 0, y: 0, w: 10, h: 10 },
+            sprite_source_size: SheetRect { x: 0, y: 0, w: 10, h: 10 },
+            hitboxes: vec![],
+        };
+        let sheet = |count: usize| Sheet {
+            frames: (0..count).map(|index| (format!("{}.png", index), cell.clone())).collect(),
+        };
+
+        assert!(CellTable::new(&sheet(u16::MAX as usize + 1)).is_ok());
+        assert!(CellTable::new(&sheet(u16::MAX as usize + 2)).is_err());
+    }
+
+    fn falling_square(lifetime: u16) -> ParticleEffect {
+        ParticleEffect {
+            min_velocity: (1.0, 0.0),
+            max_velocity: (1.0, 0.0),
+            gravity: 0.5,
+            lifetime,
+            size: 4,
+            color: "#FFFFFF",
+            fade: true,
+            sprite: None,
+        }
+    }
+
+    #[test]
+    fn particles_move_fall_and_die_without_outgrowing_the_pool() {
+        let mut particles = ParticleSystem::new(4);
+        let square = particles.add_effect(falling_square(3));
+
+        particles.burst(square, Point { x: 10, y: 20 }, 6);
+        assert_eq!(particles.live_count(), 4);
+
+        particles.update();
+        particles.update();
+        let particle = particles.particles[0];
+        assert_eq!((particle.x, particle.y), (12.0, 21.5));
+
+        particles.update();
+        assert_eq!(particles.live_count(), 0);
+    }
+
+    #[test]
+    fn emitters_spawn_at_their_rate_until_stopped() {
+        let mut particles = ParticleSystem::new(32);
+        let square = particles.add_effect(falling_square(100));
+
+        let trail = particles.start(square, Point { x: 0, y: 0 }, 0.5);
+        for _ in 0..4 {
+            particles.update();
+        }
+        assert_eq!(particles.live_count(), 2);
+
+        particles.stop(trail);
+        particles.update();
+        assert_eq!(particles.live_count(), 2);
+        assert_eq!(particles.start(square, Point { x: 0, y: 0 }, 1.0), trail);
+    }
+
+    #[test]
+    fn camera_follows_forwards_only_and_converts_to_screen() {
+        let mut camera = Camera::new(600, 600);
+        camera.follow(Point { x: 100, y: 479 }, 120);
+        assert_eq!(camera.left(), 0);
+
+        camera.follow(Point { x: 500, y: 479 }, 120);
+        assert_eq!((camera.left(), camera.right()), (380, 980));
+
+        camera.follow(Point { x: 450, y: 479 }, 120);
+        assert_eq!(camera.left(), 380);
+
+        let screen = camera.to_screen(Point { x: 500, y: 479 });
+        assert_eq!((screen.x, screen.y), (120, 479));
+    }
+
+    #[test]
+    fn parallax_layers_wrap_and_cover_the_view() {
+        let offset = scrolled(0.0, 700.0 * 0.5, 600, true);
+        assert_eq!(offset, 350.0);
+        assert_eq!(scrolled(offset, 300.0, 600, true), 50.0);
+        assert_eq!(scrolled(offset, 300.0, 600, false), 650.0);
+
+        assert_eq!(tile_positions(0.0, 600, 600, true).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(tile_positions(50.0, 600, 600, true).collect::<Vec<_>>(), vec![-50, 550]);
+        assert_eq!(tile_positions(50.0, 250, 800, true).collect::<Vec<_>>(), vec![-50, 200, 450, 700]);
+        assert_eq!(tile_positions(650.0, 600, 600, false).collect::<Vec<_>>(), vec![-650]);
+        assert_eq!(tile_positions(0.0, 0, 600, true).count(), 0);
+    }
+
+    #[test]
+    fn render_queue_draws_by_layer_keeping_submission_order() {
+        let mut camera = Camera::new(600, 600);
+        camera.move_horizontally(250);
+        let rect = Rect::default();
+
+        let mut queue = RenderQueue::new();
+        queue.fill_rect(Layer::Hud, &rect, "hud");
+        queue.with_camera(&camera, |queue| {
+            queue.fill_rect(Layer::Player, &rect, "boy");
+            queue.fill_rect(Layer::Obstacles, &rect, "platform");
+        });
+        queue.fill_rect(Layer::Background, &rect, "sky");
+        queue.fill_rect(Layer::Player, &rect, "placeholder");
+        queue.sort();
+
+        let drawn: Vec<_> = queue.commands.iter().map(|queued| match queued.command {
+            DrawCommand::FillRect { color, .. } => (color, queued.offset.x),
+            _ => ("", 0),
+        }).collect();
+        assert_eq!(drawn, [("sky", 0), ("platform", 250), ("boy", 250), ("placeholder", 0), ("hud", 0)]);
+    }
+
+    #[test]
+    fn render_queue_text_shares_one_buffer() {
+        let mut queue = RenderQueue::new();
+        queue.draw_text(Layer::Hud, format_args!("Score {}", 12), &Point::default());
+        queue.draw_text(Layer::Hud, "Frame Rate 60", &Point::default());
+
+        let texts: Vec<_> = queue.commands.iter().map(|queued| match &queued.command {
+            DrawCommand::Text { text, .. } => &queue.text[text.clone()],
+            _ => "",
+        }).collect();
+        assert_eq!(texts, ["Score 12", "Frame Rate 60"]);
+        assert_eq!(queue.text, "Score 12Frame Rate 60");
+    }
+
+    #[test]
+    fn draw_params_default_to_drawing_as_is() {
+        assert!(DrawParams::default().is_identity());
+
+        let flipped = DrawParams { flip_x: true, scale: 2.0, ..Default::default() };
+        assert!(!flipped.is_identity());
+        assert_eq!(flipped.scale_xy(), (-2.0, 2.0));
+    }
+
+    #[test]
+    fn viewport_letterboxes_and_scales_for_the_pixel_ratio() {
+        let wide = Viewport::fit(1200.0, 600.0, 2.0);
+        assert_eq!((wide.backing_width, wide.backing_height), (2400, 1200));
+        assert_eq!((wide.scale, wide.offset_x, wide.offset_y), (2.0, 600.0, 0.0));
+        assert_eq!(wide.css_scale(), 1.0);
+
+        let phone = Viewport::fit(375.0, 667.0, 3.0);
+        assert_eq!((phone.backing_width, phone.backing_height), (1125, 2001));
+        assert_eq!((phone.scale, phone.offset_x, phone.offset_y), (1.875, 0.0, 438.0));
+
+        let hidden = Viewport::fit(0.0, 0.0, 0.0);
+        assert_eq!((hidden.backing_width, hidden.backing_height, hidden.pixel_ratio), (1, 1, 1.0));
+    }
+
+    #[test]
+    fn loading_progress_counts_an_empty_manifest_as_done() {
+        let manifest = AssetManifest::default();
+        let progress = LoadingProgress::start(&manifest, true);
+        assert_eq!(progress.fraction(), 1.0);
+
+        let progress = LoadingProgress { loaded: 1, total: 4 };
+        assert_eq!(progress.fraction(), 0.25);
+    }
+
+    #[test]
+    fn loading_progress_leaves_out_sounds_without_audio() {
+        let manifest = AssetManifest {
+            images: HashMap::from([("stone".to_string(), "Stone.png".to_string())]),
+            sounds: HashMap::from([
+                ("jump".to_string(), "jump.mp3".to_string()),
+                ("coin".to_string(), "coin.mp3".to_string()),
+            ]),
+            ..AssetManifest::default()
+        };
+        assert_eq!(LoadingProgress::start(&manifest, true).total, 3);
+
+        let mut silent = LoadingProgress::start(&manifest, false);
+        assert_eq!(silent.total, 1);
+        silent.loaded += 1;
+        assert_eq!(silent.fraction(), 1.0);
     }
 }//^-- mod tests
 
@@ -3,12 +3,10 @@ use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use web_sys::HtmlImageElement;
 use rand::prelude::{thread_rng, Rng};
-use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
 
 use self::red_hat_boy_states::*;
 
-use gloo_utils::format::JsValueSerdeExt;
-
 /*
 #[cfg(test)]
 mod test_browser;
@@ -21,40 +19,74 @@ use crate::browser;
 */
 use crate::{
     browser,
-    engine::{ self, Cell, Game, Image, KeyState, Point, Rect, 
-              Renderer, Sheet, SpriteSheet, Sound, Audio},
-    segments::{stone_and_platform, platform_and_stone,},
+    config::{self, GameConfig},
+    engine::{ self, Animation, AssetManifest, Camera, Cell, CellTable, DrawParams, EffectId, EmitterId, 
+              Game, Hitbox, Image, KeyState, Layer, ParallaxBackground, ParallaxLayer, ParticleEffect, 
+              ParticleSystem, Path, Point, Rect, RenderQueue, Sheet, SheetReport, Side, SpatialGrid, SpriteSheet, Sound, Audio,
+              TransitionTable},
+    segments::{stone_and_platform, platform_and_stone, moving_platforms, slide_under, 
+               FLOATING_PLATFORM_SPRITES, OVERHEAD_SPRITE,},
 };
 
 
-const HEIGHT: i16 = 600;
-const TIMELINE_MINIMUM: i16 = 1000;
-const OBSTACLE_BUFFER: i16 = 20;
+const WIDTH: i16 = engine::LOGICAL_WIDTH;
+const HEIGHT: i16 = engine::LOGICAL_HEIGHT;
+const ASSET_MANIFEST: &str = "../resources/assets.json";
+// Background layers back to front: image asset, scroll factor, y.
+const BACKGROUND_LAYERS: &[(&str, f32, i16)] = &[("background", 1.0, 0)];
+const PARTICLE_CAPACITY: usize = 256;
+// Past this the whole world is shifted back to 0, well before i16 runs out.
+const ORIGIN_LIMIT: i16 = 16384;
+// About a platform wide, so the boy's sweep only ever spans a column or two.
+const OBSTACLE_GRID_COLUMN: i16 = 256;
+
+// The stone image, and its hitboxes if the manifest gives it any.
+#[derive(Clone)]
+pub struct Stone {
+    pub image: HtmlImageElement,
+    pub hitboxes: Vec<Hitbox>,
+}
 
+// Without hitboxes a barrier collides on its whole image.
 pub struct Barrier {
     image: Image,
+    bounding_boxes: Vec<Rect>,
 }
 
 impl Barrier {
-    pub fn new(image: Image) -> Self {
-        Barrier { image }
+    pub fn new(image: Image, hitboxes: &[Hitbox]) -> Self {
+        let bounding_boxes = if hitboxes.is_empty() {
+            vec![*image.bounding_box()]
+        } else {
+            hitboxes
+                .iter()
+                .map(|hitbox| hitbox.at(image.bounding_box().position))
+                .collect()
+        };
+
+        Barrier { image, bounding_boxes }
     }
 }
 
 
 impl Obstacle for Barrier {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if boy.bounding_box().intersects(self.image.bounding_box()) {
-            boy.knock_out()
-        }
+    fn kind(&self) -> ObstacleKind {
+        ObstacleKind::Hazard
+    }
+
+    fn bounding_boxes(&self) -> &[Rect] {
+        &self.bounding_boxes
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        self.image.draw(renderer);
+    fn draw(&self, queue: &mut RenderQueue) {
+        self.image.draw(queue, Layer::Obstacles);
     }
 
     fn move_horizontally(&mut self, x: i16) {
         self.image.move_horizontally(x);
+        self.bounding_boxes
+            .iter_mut()
+            .for_each(|bounding_box| bounding_box.set_x(bounding_box.x() + x));
     }
     fn right(&self) -> i16 {
         self.image.right()
@@ -62,32 +94,323 @@ impl Obstacle for Barrier {
 }//^-- impl Obstacle for Barrier
 
 
+/*
+Something to pick up, worth `value` points. There's no coin art in
+the sheets yet, so it's drawn as a gold square.
+*/
+pub struct Collectible {
+    bounding_box: Rect,
+    value: u32,
+}
+
+impl Collectible {
+    const SIZE: i16 = 24;
+    const COLOR: &'static str = "#F1C40F";
+
+    pub fn new(position: Point, value: u32) -> Self {
+        Collectible {
+            bounding_box: Rect::new(position, Collectible::SIZE, Collectible::SIZE),
+            value,
+        }
+    }
+}
+
+impl Obstacle for Collectible {
+    fn kind(&self) -> ObstacleKind {
+        ObstacleKind::Collectible { value: self.value }
+    }
+
+    fn bounding_boxes(&self) -> &[Rect] {
+        std::slice::from_ref(&self.bounding_box)
+    }
+
+    // Picked up, so it's gone.
+    fn on_collision(&mut self, _event: &CollisionEvent) -> Reaction {
+        Reaction::Remove
+    }
+
+    fn draw(&self, queue: &mut RenderQueue) {
+        queue.fill_rect(Layer::Obstacles, &self.bounding_box, Collectible::COLOR);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.bounding_box.set_x(self.bounding_box.x() + x);
+    }
+    fn right(&self) -> i16 {
+        self.bounding_box.right()
+    }
+}//^-- impl Obstacle for Collectible
+
+
+/*
+A wall hanging from above, too tall to jump over and low enough that
+only sliding gets under it. Drawn as a column of `sprite` tiles from
+the top of its box down.
+*/
+pub struct Overhead {
+    sheet: Rc<SpriteSheet>,
+    sprite: Option<Cell>,
+    bounding_box: Rect,
+}
+
+impl Overhead {
+    pub fn new(sheet: Rc<SpriteSheet>, sprite_name: &str, bounding_box: Rect) -> Self {
+        Overhead {
+            sprite: sheet.cell(sprite_name).cloned(),
+            sheet,
+            bounding_box,
+        }
+    }
+}
+
+impl Obstacle for Overhead {
+    fn kind(&self) -> ObstacleKind {
+        ObstacleKind::Hazard
+    }
+
+    fn bounding_boxes(&self) -> &[Rect] {
+        std::slice::from_ref(&self.bounding_box)
+    }
+
+    fn draw(&self, queue: &mut RenderQueue) {
+        let sprite = match &self.sprite {
+            Some(sprite) => sprite,
+            None => return,
+        };
+        let frame = Rect::new_from_x_y(sprite.frame.x, sprite.frame.y, sprite.frame.w, sprite.frame.h);
+
+        let mut y = self.bounding_box.y();
+        while y < self.bounding_box.bottom() {
+            self.sheet.draw(
+                queue,
+                Layer::Obstacles,
+                &frame,
+                &Rect::new_from_x_y(self.bounding_box.x(), y, self.bounding_box.width, sprite.frame.h),
+                &DrawParams::default(),
+            );
+            y += sprite.frame.h.max(1);
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.bounding_box.set_x(self.bounding_box.x() + x);
+    }
+    fn right(&self) -> i16 {
+        self.bounding_box.right()
+    }
+}//^-- impl Obstacle for Overhead
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObstacleKind {
+    // Can be landed on from above, knocks the boy out otherwise.
+    Solid,
+    // Knocks the boy out however he touches it.
+    Hazard,
+    Collectible { value: u32 },
+}
+
+/*
+The boy touching an obstacle. `obstacle` is its index in Walk.obstacles
+for the tick the event is from and `bounds` the box he touched. `side`
+is the side of the box he came in through and `time` how far through
+the tick that was, from the sweep. `penetration` is how far in he ends
+up, zero if he went straight through.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct CollisionEvent {
+    pub obstacle: usize,
+    pub kind: ObstacleKind,
+    pub bounds: Rect,
+    pub penetration: Point,
+    pub side: Side,
+    pub time: f32,
+}
+
+// What an obstacle wants done with it after a collision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reaction {
+    Keep,
+    Remove,
+}
+
 pub trait Obstacle {
-    fn check_intersection(&self, boy: &mut RedHatBoy);
-    fn draw(&self, renderer: &Renderer);
-    //fn draw_rect(&self, renderer: &Renderer);
+    fn kind(&self) -> ObstacleKind;
+    fn bounding_boxes(&self) -> &[Rect];
+    /*
+    Everything it could be hit on, for the broad phase. Anything that
+    moves should cover everywhere it goes, so the grid doesn't need
+    rebuilding as it does.
+    */
+    fn bounds(&self) -> Rect {
+        covering(self.bounding_boxes())
+    }
+    // Moves anything that moves on its own, once a tick.
+    fn update(&mut self) {}
+    // How far the last update moved it, for carrying whoever stands on it.
+    fn velocity(&self) -> Point {
+        Point::default()
+    }
+    fn on_collision(&mut self, _event: &CollisionEvent) -> Reaction {
+        Reaction::Keep
+    }
+    fn draw(&self, queue: &mut RenderQueue);
+    #[cfg(feature = "debug-overlay")]
+    fn draw_rect(&self, queue: &mut RenderQueue) {
+        for bounding_box in self.bounding_boxes() {
+            queue.draw_rect(Layer::Hud, bounding_box);
+        }
+    }
     fn move_horizontally(&mut self, x: i16);
     fn right(&self) -> i16;
 }
 
+/*
+Every obstacle `boy` touched this tick, at most one event each, for the
+first of its boxes he hit. `boy` is where he is now and `velocity` how
+he got there, so his box is swept from where it was a tick ago. That's
+close enough even when the animation frame changed size in between.
+Only the obstacles `grid` finds near that sweep are checked. Nothing is
+changed, so the order of `obstacles` doesn't matter;
+touch_obstacles decides what happens.
+*/
+fn detect_collisions(boy: &Rect, velocity: Point, obstacles: &[Box<dyn Obstacle>], grid: &SpatialGrid,
+                     nearby: &mut Vec<usize>, events: &mut Vec<CollisionEvent>) {
+    let start = Rect::new_from_x_y(boy.x() - velocity.x, boy.y() - velocity.y, boy.width, boy.height);
+    grid.query(&start.union(boy), nearby);
+
+    for &index in nearby.iter() {
+        let obstacle = &obstacles[index];
+        let hit = obstacle.bounding_boxes().iter().find_map(|bounds| {
+            start.sweep(velocity, bounds).map(|sweep| (*bounds, sweep))
+        });
+
+        if let Some((bounds, sweep)) = hit {
+            events.push(CollisionEvent {
+                obstacle: index,
+                kind: obstacle.kind(),
+                bounds,
+                penetration: boy.penetration(&bounds).map_or(Point::default(), |(depth, _side)| depth),
+                side: sweep.normal,
+                time: sweep.time,
+            });
+        }
+    }
+}//^-- fn detect_collisions
+
+// What the boy's collisions came to in a tick, see touch_obstacles.
+#[derive(Debug, Default, PartialEq)]
+struct Contacts {
+    knocked_out: bool,
+    // The highest top landed on, and how that obstacle just moved.
+    landing: Option<(i16, Point)>,
+    // The value of each pickup and the middle of it.
+    collected: Vec<(u32, Point)>,
+    // Some obstacle removed itself, so indices into `obstacles` changed.
+    removed: bool,
+}
+
+/*
+All of a tick's collisions, decided together: coming down onto the top
+of something solid lands on it and hitting it any other way knocks the
+boy out, unless he's `invulnerable`, when he goes straight through.
+Being knocked out wins over landing, the highest box wins between
+landings, and every pickup counts. Then each obstacle gets to react to
+its event, and the ones that ask to be are removed from `obstacles`.
+Nothing here touches the boy or the score; Walk applies the Contacts,
+and RedHatBoyStateMachine::touch is what they do to him.
+*/
+fn touch_obstacles(boy: &Rect, velocity: Point, invulnerable: bool, obstacles: &mut Vec<Box<dyn Obstacle>>,
+                   grid: &SpatialGrid, nearby: &mut Vec<usize>, events: &mut Vec<CollisionEvent>) -> Contacts {
+    events.clear();
+    detect_collisions(boy, velocity, obstacles, grid, nearby, events);
+
+    let mut contacts = Contacts::default();
+    for event in events.iter() {
+        match event.kind {
+            ObstacleKind::Hazard => contacts.knocked_out = !invulnerable,
+            ObstacleKind::Solid if event.side == Side::Top => {
+                if contacts.landing.map_or(true, |(y, _velocity)| event.bounds.y() < y) {
+                    contacts.landing = Some((event.bounds.y(), obstacles[event.obstacle].velocity()));
+                }
+            }
+            ObstacleKind::Solid => contacts.knocked_out = !invulnerable,
+            ObstacleKind::Collectible { value } => {
+                let centre = Point {
+                    x: event.bounds.x() + event.bounds.width / 2,
+                    y: event.bounds.y() + event.bounds.height / 2,
+                };
+                contacts.collected.push((value, centre));
+            }
+        }
+    }
+
+    // Only allocated on the ticks something is removed.
+    let mut removed: Option<Vec<bool>> = None;
+    for event in events.iter() {
+        if obstacles[event.obstacle].on_collision(event) == Reaction::Remove {
+            removed.get_or_insert_with(|| vec![false; obstacles.len()])[event.obstacle] = true;
+        }
+    }
+    if let Some(removed) = removed {
+        let mut index = 0;
+        obstacles.retain(|_obstacle| {
+            index += 1;
+            !removed[index - 1]
+        });
+        contacts.removed = true;
+    }
+
+    contacts
+}//^-- fn touch_obstacles
+
+// The smallest rect covering all of `boxes`.
+fn covering(boxes: &[Rect]) -> Rect {
+    let mut boxes = boxes.iter();
+    let first = boxes.next().copied().unwrap_or_default();
+    boxes.fold(first, |bounds, bounding_box| bounds.union(bounding_box))
+}
+
+/*
+Tiles to run along. A platform with a Path moves along it, `offset`
+from where it was put `tick` ticks in, and `velocity` is how far the
+last tick moved it.
+*/
 pub struct Platform {
     sheet: Rc<SpriteSheet>,
     bounding_boxes: Vec<Rect>,
     sprites: Vec<Cell>,
     position: Point,
+    path: Path,
+    tick: u16,
+    offset: Point,
+    velocity: Point,
 }
 
 impl Platform {
 
+    // Collides on its sprites' hitboxes, or `bounding_boxes` if they have none.
     pub fn new( sheet: Rc<SpriteSheet>, position: Point,
-                sprite_names: &[&str], bounding_boxes: &[Rect],) -> Self {
+                sprite_names: &[&str], bounding_boxes: &[Rect], path: Path,) -> Self {
 
-        let sprites = sprite_names
+        let sprites: Vec<Cell> = sprite_names
                             .iter()
                             .filter_map(|sprite_name|
                             sheet.cell(sprite_name).cloned())
                             .collect();
-        let bounding_boxes = bounding_boxes
+
+        // Each sprite is drawn where the last one ended.
+        let mut x = 0;
+        let mut hitboxes = vec![];
+        for sprite in sprites.iter() {
+            let sprite_position = Point { x: position.x + x, y: position.y };
+            hitboxes.extend(sprite.hitboxes.iter().map(|hitbox| hitbox.at(sprite_position)));
+            x += sprite.frame.w;
+        }
+
+        let bounding_boxes = if !hitboxes.is_empty() {
+            hitboxes
+        } else {
+            bounding_boxes
                                 .iter()
                                 .map(|bounding_box| {
                                     Rect::new_from_x_y(
@@ -97,35 +420,74 @@ impl Platform {
                                         bounding_box.height,
                                     )
                                 })
-                                .collect();
+                                .collect()
+        };
 
         Platform {
             sheet,
             position,
             sprites,
             bounding_boxes,
+            path,
+            tick: 0,
+            offset: Point::default(),
+            velocity: Point::default(),
         }
     }//^-- fn new
 
-    fn bounding_boxes(&self) -> &Vec<Rect> {
+    fn shift(&mut self, distance: Point) {
+        self.position.x += distance.x;
+        self.position.y += distance.y;
+        self.bounding_boxes.iter_mut()
+                           .for_each(|bounding_box| {
+                                bounding_box.set_x(bounding_box.x() + distance.x);
+                                bounding_box.position.y += distance.y;
+                            });
+    }
+}
+
+impl Obstacle for Platform {
+
+    fn kind(&self) -> ObstacleKind {
+        ObstacleKind::Solid
+    }
+
+    fn bounding_boxes(&self) -> &[Rect] {
         &self.bounding_boxes
     }
-    #[allow(dead_code)]
-    fn draw_rect(&self, renderer: &Renderer){
-        for bounding_box in self.bounding_boxes() {
-            renderer.draw_rect(&bounding_box);
-        }
+
+    fn bounds(&self) -> Rect {
+        let (low, high) = self.path.extent();
+        let here = covering(&self.bounding_boxes);
+        Rect::new_from_x_y(
+            here.x() - self.offset.x + low.x,
+            here.y() - self.offset.y + low.y,
+            here.width + high.x - low.x,
+            here.height + high.y - low.y,
+        )
     }
 
-}
+    fn update(&mut self) {
+        self.tick = (self.tick + 1) % self.path.period();
+        let offset = self.path.offset(self.tick);
+        self.velocity = Point {
+            x: offset.x - self.offset.x,
+            y: offset.y - self.offset.y,
+        };
+        self.offset = offset;
+        self.shift(self.velocity);
+    }
 
-impl Obstacle for Platform {
+    fn velocity(&self) -> Point {
+        self.velocity
+    }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn draw(&self, queue: &mut RenderQueue) {
         let mut x = 0;
-        self.sprites.iter().for_each(|sprite| {
+        for sprite in self.sprites.iter() {
             self.sheet.draw(
-                renderer,
+                queue,
+                Layer::Obstacles,
                 &Rect::new_from_x_y(
                     sprite.frame.x,
                     sprite.frame.y,
@@ -140,172 +502,275 @@ impl Obstacle for Platform {
                     sprite.frame.w,
                     sprite.frame.h,
                 ),
+                &DrawParams::default(),
             );
             x += sprite.frame.w;
-        });
+        }
     }//^-- fn draw
 
     fn move_horizontally(&mut self, x: i16) {
-        self.position.x += x;
-        self.bounding_boxes.iter_mut()
-                           .for_each(|bounding_box| {
-                                bounding_box.set_x(bounding_box.position.x + x);
-                            });
+        self.shift(Point { x, y: 0 });
     }
 
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if let Some(box_to_land_on) = self
-            .bounding_boxes()
-            .iter()
-            .find(|&bounding_box| boy.bounding_box().intersects(bounding_box))
-        {
-            if boy.velocity_y() > 0 && boy.pos_y() < self.position.y {
-                boy.land_on(box_to_land_on.y());
-            } else {
-                boy.knock_out();
-            }
-        }
-    }//^-- check_intersection
-
+    // As far right as it ever goes, so it isn't culled or built over early.
     fn right(&self) -> i16 {
-        self.bounding_boxes()
-            .last()
-            .unwrap_or(&Rect::default())
-            .right()
+        self.bounds().right()
     }
 
 }//^-- impl Obstacle for Platform
 
 pub struct RedHatBoy {
     state_machine: RedHatBoyStateMachine,
-    sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    sprite_sheet: Rc<SpriteSheet>,
+    // What his transitions since the last take_effects should show.
+    effects: Vec<BoyEffect>,
+    audio: Audio,
+    // None without audio.
+    jump_sound: Option<Sound>,
+    // Optional: without it the double jump sounds like the first one.
+    double_jump_sound: Option<Sound>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BoyEffect {
+    Jumped,
+    DoubleJumped,
+    Landed,
+    SlideStarted,
+    SlideEnded,
+    KnockedDown,
+    Hurt,
+}
+
+impl BoyEffect {
+    fn between(from: &RedHatBoyStateMachine, to: &RedHatBoyStateMachine) -> impl Iterator<Item = BoyEffect> {
+        use RedHatBoyStateMachine::{DoubleJumping, Falling, Hurt, Jumping, Running, Sliding};
+
+        let sliding = (matches!(from, Sliding(_)), matches!(to, Sliding(_)));
+        [
+            (matches!((from, to), (Running(_), Jumping(_))), BoyEffect::Jumped),
+            // Only an air jump spends one.
+            (to.context().air_jumps < from.context().air_jumps, BoyEffect::DoubleJumped),
+            (matches!(from, Jumping(_) | DoubleJumping(_)) && matches!(to, Running(_)), BoyEffect::Landed),
+            (sliding == (false, true), BoyEffect::SlideStarted),
+            (sliding == (true, false), BoyEffect::SlideEnded),
+            (!matches!(from, Falling(_)) && matches!(to, Falling(_)), BoyEffect::KnockedDown),
+            (!matches!(from, Hurt(_)) && matches!(to, Hurt(_)), BoyEffect::Hurt),
+        ]
+        .into_iter()
+        .filter_map(|(happened, effect)| happened.then_some(effect))
+    }
 }
 
 impl RedHatBoy {
  
-    fn new(sprite_sheet: Sheet, image: HtmlImageElement, audio: Audio, sound: Sound) -> Self {
+    fn new(sprite_sheet: Rc<SpriteSheet>, 
+           audio: Audio, 
+           jump_sound: Option<Sound>, 
+           double_jump_sound: Option<Sound>, 
+           config: GameConfig) -> Self {
+        let animation = Animation::new(red_hat_boy_states::clips(), sprite_sheet.cells());
         RedHatBoy {
-            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound)),
+            state_machine: RedHatBoyStateMachine::Idle(
+                RedHatBoyState::new(animation, config)
+            ),
             sprite_sheet,
-            image,
+            effects: vec![],
+            audio,
+            jump_sound,
+            double_jump_sound,
         }
     }
     fn reset(boy: Self) -> Self {
+        let config = boy.state_machine.context().config;
         RedHatBoy::new(
             boy.sprite_sheet,
-            boy.image,
-            boy.state_machine.context().audio.clone(),
-            boy.state_machine.context().jump_sound.
-            clone(),
+            boy.audio,
+            boy.jump_sound,
+            boy.double_jump_sound,
+            config,
         )
     }
 
+    fn set_config(&mut self, config: GameConfig) {
+        self.state_machine.context_mut().set_config(config);
+    }
+
+    fn transition(&mut self, event: Event) {
+        self.change(|state_machine| state_machine.transition(event));
+    }
+
+    // Every state change goes through here, so none of them miss their effects.
+    fn change(&mut self, to: impl FnOnce(RedHatBoyStateMachine) -> RedHatBoyStateMachine) {
+        let next = to(self.state_machine.clone());
+        for effect in BoyEffect::between(&self.state_machine, &next) {
+            self.play_sound(effect);
+            self.effects.push(effect);
+        }
+        self.state_machine = next;
+    }
+
+    // Without a "double_jump" sound in the manifest, the double jump is
+    // the jump sound pitched up.
+    fn play_sound(&self, effect: BoyEffect) {
+        const DOUBLE_JUMP_PITCH: f32 = 1.5;
+        let played = match (effect, &self.double_jump_sound, &self.jump_sound) {
+            (BoyEffect::Jumped, _, Some(jump)) => Some(self.audio.play_sound(jump)),
+            (BoyEffect::DoubleJumped, Some(double_jump), _) => Some(self.audio.play_sound(double_jump)),
+            (BoyEffect::DoubleJumped, None, Some(jump)) => Some(self.audio.play_sound_at_rate(jump, DOUBLE_JUMP_PITCH)),
+            _ => None,
+        };
+
+        if let Some(Err(err)) = played {
+            log!("Error playing jump sound {:#?}", err);
+        }
+    }
+
+    fn take_effects(&mut self) -> std::vec::Drain<'_, BoyEffect> {
+        self.effects.drain(..)
+    }
+
     fn run_right(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Run);
+        self.transition(Event::Run);
     }
 
     fn slide(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Slide);
+        self.transition(Event::Slide);
     }
 
     fn jump(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Jump);
+        self.transition(Event::Jump);
+    }
+
+    // Only does anything in the air, where Jump doesn't.
+    fn air_jump(&mut self) {
+        self.transition(Event::AirJump);
+    }
+
+    // -1 to slow down, 1 to speed up; only does anything in the air.
+    fn steer(&mut self, direction: i16) {
+        self.transition(Event::Steer(direction));
     }
 
     fn update(&mut self) {
-        self.state_machine = self.state_machine.clone().update();
+        self.transition(Event::Update);
     }
 
-    fn frame_name(&self) -> String {
-        format!(
-            "{} ({}).png",
-            self.state_machine.frame_name(),
-            (self.state_machine.context().frame / 3) + 1
-        )
+    fn animation(&self) -> &Animation {
+        &self.state_machine.context().animation
     }
 
-    fn current_sprite(&self) -> Option<&Cell> {
-        self
-            .sprite_sheet
-            .frames
-            .get(&self.frame_name())
+    fn current_sprite(&self) -> Result<&Cell> {
+        self.state_machine.sprite(self.sprite_sheet.cells()).ok_or_else(|| anyhow!(
+            "Cell not found: {}",
+            self.animation().frame_name().unwrap_or("no frame for the current animation")
+        ))
     }
 
     fn bounding_box(&self) -> Rect {
-        const X_OFFSET: i16 = 18;     
-        const Y_OFFSET: i16 = 14;     
-        const WIDTH_OFFSET: i16 = 28; 
-        let mut bounding_box = self.destination_box();
-        bounding_box.position.x += X_OFFSET;
-        bounding_box.width -= WIDTH_OFFSET;
-        bounding_box.position.y += Y_OFFSET;
-        bounding_box.height -= Y_OFFSET;
-        bounding_box
+        self.state_machine.bounding_box(self.sprite_sheet.cells())
     }
 
-    fn destination_box(&self) -> Rect {
-        let sprite = self.current_sprite().expect("Cell not found");
+    fn destination_box(&self) -> Result<Rect> {
+        Ok(self.state_machine.destination_box(self.current_sprite()?))
+    }
 
-        Rect {
-            position: Point {
-                x: (self.state_machine.context().position.x + sprite.sprite_source_size.x)
-                .into(),
-                y: (self.state_machine.context().position.y + sprite.sprite_source_size.y)
-                .into(),
-            },
-            width: sprite.frame.w.into(),
-            height: sprite.frame.h.into(),
-        }
+    fn placeholder_box(&self) -> Rect {
+        self.state_machine.placeholder_box()
     }
 
-    #[allow(dead_code)]
-    fn draw_rect(&self, renderer: &Renderer){
-        renderer.draw_rect(&self.bounding_box());
+    #[cfg(feature = "debug-overlay")]
+    fn draw_rect(&self, queue: &mut RenderQueue){
+        queue.draw_rect(Layer::Hud, &self.bounding_box());
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        let sprite = self.current_sprite().expect("Cell not found");
-        
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()> {
+        let sprite = match self.current_sprite() {
+            Ok(sprite) => sprite,
+            Err(err) => {
+                queue.fill_rect(Layer::Player, &self.placeholder_box(), "#FF00FF");
+                return Err(err);
+            }
+        };
 
-        renderer.draw_image(
-            &self.image,
+        self.sprite_sheet.draw(
+            queue,
+            Layer::Player,
             &Rect {
                 position: Point {
-                    x: sprite.frame.x.into(),
-                    y: sprite.frame.y.into(),
+                    x: sprite.frame.x,
+                    y: sprite.frame.y,
                 },
-                width: sprite.frame.w.into(),
-                height: sprite.frame.h.into(),
+                width: sprite.frame.w,
+                height: sprite.frame.h,
             },
-            &self.destination_box(),
+            &self.destination_box()?,
+            &self.draw_params(),
         );
+
+        Ok(())
     }//^-- fn draw
 
-    fn knock_out(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::KnockOut);
+    fn draw_params(&self) -> DrawParams {
+        self.state_machine.draw_params()
     }
+
+    fn lives(&self) -> i16 {
+        self.state_machine.context().lives
+    }
+
+    fn invulnerable(&self) -> bool {
+        self.state_machine.context().invulnerable > 0
+    }
+
     fn knocked_out(&self) -> bool {
         self.state_machine.knocked_out()
     }
 
-    fn land_on(&mut self, position: i16) { 
-        self.state_machine = self.state_machine.clone().transition(Event::Land(position));
+    fn touch(&mut self, contacts: &Contacts) {
+        self.change(|state_machine| state_machine.touch(contacts));
     }
 
-    fn pos_y(&self) -> i16 {
-        self.state_machine.context().position.y
+    // The middle of the bottom of his bounding box.
+    fn feet(&self) -> Point {
+        let bounding_box = self.bounding_box();
+        Point {
+            x: bounding_box.x() + bounding_box.width / 2,
+            y: bounding_box.y() + bounding_box.height,
+        }
     }
 
-    fn velocity_y(&self) -> i16 {
-        self.state_machine.context().velocity.y
+    fn velocity(&self) -> Point {
+        self.state_machine.context().velocity
     }
 
-    fn walking_speed(&self) -> i16 {
-        self.state_machine.context().velocity.x
+    fn position(&self) -> Point {
+        self.state_machine.context().position
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.state_machine.context_mut().position.x += x;
     }
 }//^-- impl RedHatBoy 
 
+/*
+Checks that every cell the game will ask for is in the sheets:
+each animation's whole frame sequence for the boy, and the
+platform and overhead pieces for the tiles.
+*/
+pub fn check_sheets(rhb: &Sheet, tiles: &Sheet) -> Vec<SheetReport> {
+    let clips = red_hat_boy_states::clips();
+    let frame_names = clips
+                        .iter()
+                        .flat_map(|clip| clip.frames())
+                        .map(String::as_str);
+
+    vec![
+        SheetReport::check("rhb", rhb, frame_names),
+        SheetReport::check("tiles", tiles, FLOATING_PLATFORM_SPRITES.into_iter().chain([OVERHEAD_SPRITE])),
+    ]
+}
+
 //#[derive(Copy, Clone)]
 #[derive(Clone)]
 enum RedHatBoyStateMachine {
@@ -313,17 +778,60 @@ enum RedHatBoyStateMachine {
     Running(RedHatBoyState<Running>),
     Sliding(RedHatBoyState<Sliding>),
     Jumping(RedHatBoyState<Jumping>),
+    DoubleJumping(RedHatBoyState<DoubleJumping>),
+    Hurt(RedHatBoyState<Hurt>),
     Falling(RedHatBoyState<Falling>),
     KnockedOut(RedHatBoyState<KnockedOut>),
 }
 
+/*
+What RedHatBoyStateMachine::transition does, pair by pair; the tests
+check one against the other. Only the tests and the diagram tool
+read it.
+*/
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub const RED_HAT_BOY_TRANSITIONS: TransitionTable = TransitionTable {
+    name: "RedHatBoyStateMachine",
+    initial: "Idle",
+    transitions: &[
+        ("Idle",          "Run",      &["Running"]),
+        ("Idle",          "Update",   &["Idle"]),
+        ("Running",       "Jump",     &["Jumping"]),
+        ("Running",       "Slide",    &["Sliding"]),
+        ("Running",       "Update",   &["Running"]),
+        ("Running",       "KnockOut", &["Hurt", "Falling"]),
+        ("Running",       "Land",     &["Running"]),
+        ("Sliding",       "Slide",    &["Sliding"]),
+        ("Sliding",       "Update",   &["Sliding", "Running"]),
+        ("Sliding",       "KnockOut", &["Hurt", "Falling"]),
+        ("Sliding",       "Land",     &["Sliding"]),
+        ("Jumping",       "AirJump",  &["Jumping", "DoubleJumping"]),
+        ("Jumping",       "Steer",    &["Jumping"]),
+        ("Jumping",       "Update",   &["Jumping", "Running"]),
+        ("Jumping",       "KnockOut", &["Hurt", "Falling"]),
+        ("Jumping",       "Land",     &["Running"]),
+        ("DoubleJumping", "AirJump",  &["DoubleJumping"]),
+        ("DoubleJumping", "Steer",    &["DoubleJumping"]),
+        ("DoubleJumping", "Update",   &["DoubleJumping", "Running"]),
+        ("DoubleJumping", "KnockOut", &["Hurt", "Falling"]),
+        ("DoubleJumping", "Land",     &["Running"]),
+        ("Hurt",          "Update",   &["Hurt", "Running"]),
+        ("Hurt",          "KnockOut", &["Hurt", "Falling"]),
+        ("Hurt",          "Land",     &["Hurt"]),
+        ("Falling",       "Update",   &["Falling", "KnockedOut"]),
+    ],
+};
+
+#[derive(Clone, Copy)]
 pub enum Event {
     Run,
     Slide,
     Update,
     Jump,
+    AirJump,
     KnockOut,
     Land(i16),
+    Steer(i16),
 }
 
 impl RedHatBoyStateMachine {
@@ -332,49 +840,176 @@ impl RedHatBoyStateMachine {
             (RedHatBoyStateMachine::Idle(state),    Event::Run) => state.run().into(),
             (RedHatBoyStateMachine::Running(state), Event::Jump) => state.jump().into(),
             (RedHatBoyStateMachine::Running(state), Event::Slide) => state.slide().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Slide) => state.keep_sliding().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::AirJump) => state.air_jump().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::AirJump) => state.air_jump().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Steer(direction)) => state.steer(direction).into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Steer(direction)) => state.steer(direction).into(),
 
             (RedHatBoyStateMachine::Idle(state),    Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Running(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Sliding(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Hurt(state),    Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
 
             (RedHatBoyStateMachine::Running(state), Event::KnockOut) => state.knock_out().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::KnockOut) => state.knock_out().into(),
             (RedHatBoyStateMachine::Sliding(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Hurt(state),    Event::KnockOut) => state.knock_out().into(),
 
             (RedHatBoyStateMachine::Jumping(state), Event::Land(position)) => state.land_on(position).into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Land(position)) => state.land_on(position).into(),
             (RedHatBoyStateMachine::Running(state), Event::Land(position)) => state.land_on(position).into(),
             (RedHatBoyStateMachine::Sliding(state), Event::Land(position)) => state.land_on(position).into(),
+            (RedHatBoyStateMachine::Hurt(state),    Event::Land(position)) => state.land_on(position).into(),
             _ => self,
         }
     }
 
-    fn frame_name(&self) -> &str {
-        match self {
-            RedHatBoyStateMachine::Idle(state) => state.frame_name(),
-            RedHatBoyStateMachine::Running(state) => state.frame_name(),
-            RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
-            RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
-            RedHatBoyStateMachine::Falling(state) => state.frame_name(),
-            RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
-        }
-    }
-
     fn context(&self) -> &RedHatBoyContext {
         match self {
             RedHatBoyStateMachine::Idle(state) => state.context(),
             RedHatBoyStateMachine::Running(state) => state.context(),
             RedHatBoyStateMachine::Jumping(state) => state.context(),
+            RedHatBoyStateMachine::DoubleJumping(state) => state.context(),
             RedHatBoyStateMachine::Sliding(state) => state.context(),
+            RedHatBoyStateMachine::Hurt(state) => state.context(),
             RedHatBoyStateMachine::Falling(state) => state.context(),
             RedHatBoyStateMachine::KnockedOut(state) => state.context(),
         }
     }
 
-    fn update(self) -> Self {
-        self.transition(Event::Update)
+    fn context_mut(&mut self) -> &mut RedHatBoyContext {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.context_mut(),
+            RedHatBoyStateMachine::Running(state) => state.context_mut(),
+            RedHatBoyStateMachine::Jumping(state) => state.context_mut(),
+            RedHatBoyStateMachine::DoubleJumping(state) => state.context_mut(),
+            RedHatBoyStateMachine::Sliding(state) => state.context_mut(),
+            RedHatBoyStateMachine::Hurt(state) => state.context_mut(),
+            RedHatBoyStateMachine::Falling(state) => state.context_mut(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.context_mut(),
+        }
+    }
+
+    #[cfg(any(test, feature = "debug-overlay"))]
+    fn name(&self) -> &'static str {
+        match self {
+            RedHatBoyStateMachine::Idle(_) => "Idle",
+            RedHatBoyStateMachine::Running(_) => "Running",
+            RedHatBoyStateMachine::Sliding(_) => "Sliding",
+            RedHatBoyStateMachine::Jumping(_) => "Jumping",
+            RedHatBoyStateMachine::DoubleJumping(_) => "DoubleJumping",
+            RedHatBoyStateMachine::Hurt(_) => "Hurt",
+            RedHatBoyStateMachine::Falling(_) => "Falling",
+            RedHatBoyStateMachine::KnockedOut(_) => "KnockedOut",
+        }
+    }
+
+    fn sprite<'a>(&self, cells: &'a CellTable) -> Option<&'a Cell> {
+        self.context().animation.cell().map(|id| cells.cell(id))
+    }
+
+    /*
+    The frame's "body" hitbox when the sheet has one. Otherwise trimmed in
+    from his frame, and sliding he's only the bottom of it, low enough to
+    get under an Overhead. Only needs the sheet's cells, not its image.
+    */
+    fn bounding_box(&self, cells: &CellTable) -> Rect {
+        const BODY_HITBOX: &str = "body";
+        const X_OFFSET: i16 = 18;     
+        const Y_OFFSET: i16 = 14;     
+        const SLIDING_Y_OFFSET: i16 = 45;
+        const WIDTH_OFFSET: i16 = 28; 
+
+        let sprite = self.sprite(cells);
+        let body = sprite.and_then(|sprite| sprite.hitboxes.iter().find(|hitbox| hitbox.name == BODY_HITBOX));
+        if let Some(body) = body {
+            return body.at(self.context().position);
+        }
+
+        let y_offset = match self {
+            RedHatBoyStateMachine::Sliding(_) => SLIDING_Y_OFFSET,
+            _ => Y_OFFSET,
+        };
+        let mut bounding_box = sprite
+                                .map(|sprite| self.destination_box(sprite))
+                                .unwrap_or_else(|| self.placeholder_box());
+        bounding_box.position.x += X_OFFSET;
+        bounding_box.width -= WIDTH_OFFSET;
+        bounding_box.position.y += y_offset;
+        bounding_box.height -= y_offset;
+        bounding_box
+    }
+
+    fn destination_box(&self, sprite: &Cell) -> Rect {
+        Rect {
+            position: Point {
+                x: self.context().position.x + sprite.sprite_source_size.x,
+                y: self.context().position.y + sprite.sprite_source_size.y,
+            },
+            width: sprite.frame.w,
+            height: sprite.frame.h,
+        }
+    }
+
+    // Stands in for the boy when his frame is missing from the sheet,
+    // so he stays visible and still collides.
+    fn placeholder_box(&self) -> Rect {
+        const PLACEHOLDER_WIDTH: i16 = 80;
+        const PLACEHOLDER_HEIGHT: i16 = 120;
+        Rect::new(self.context().position, 
+                  PLACEHOLDER_WIDTH, 
+                  PLACEHOLDER_HEIGHT)
+    }
+
+    /*
+    What a tick's collisions do to him, where being knocked out wins over
+    landing. Landing puts him on the top wherever it is now, so only the
+    sideways part of a moving platform carries him.
+    */
+    fn touch(self, contacts: &Contacts) -> Self {
+        if contacts.knocked_out {
+            self.transition(Event::KnockOut)
+        } else if let Some((y, velocity)) = contacts.landing {
+            let mut landed = self.transition(Event::Land(y));
+            landed.context_mut().position.x += velocity.x;
+            landed
+        } else {
+            self
+        }
     }
+
+    /*
+    Fades by KNOCKED_OUT_FADE over the falling clip and stays that faded
+    knocked out. Otherwise he turns a somersault over the double jump
+    clip, and blinks every FLASH_TICKS while he's invulnerable.
+    */
+    fn draw_params(&self) -> DrawParams {
+        const KNOCKED_OUT_FADE: f32 = 0.6;
+        const FLASH_TICKS: i16 = 4;
+        const FLASH_ALPHA: f32 = 0.3;
+        let invulnerable = self.context().invulnerable;
+        let progress = self.context().animation.progress();
+        match self {
+            RedHatBoyStateMachine::Falling(_) | RedHatBoyStateMachine::KnockedOut(_) => DrawParams {
+                alpha: 1.0 - KNOCKED_OUT_FADE * progress,
+                ..Default::default()
+            },
+            _ => DrawParams {
+                rotation: match self {
+                    RedHatBoyStateMachine::DoubleJumping(_) => std::f32::consts::TAU * progress,
+                    _ => 0.0,
+                },
+                alpha: if invulnerable / FLASH_TICKS % 2 == 1 { FLASH_ALPHA } else { 1.0 },
+                ..Default::default()
+            },
+        }
+    }
+
     fn knocked_out(&self) -> bool {
         // matches! is a macro to check `self` against an enum variant, 
         // and return whether or not they match.
@@ -406,6 +1041,18 @@ impl From<RedHatBoyState<Jumping>> for RedHatBoyStateMachine {
     }
 }
 
+impl From<RedHatBoyState<DoubleJumping>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<DoubleJumping>) -> Self {
+        RedHatBoyStateMachine::DoubleJumping(state)
+    }
+}
+
+impl From<RedHatBoyState<Hurt>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Hurt>) -> Self {
+        RedHatBoyStateMachine::Hurt(state)
+    }
+}
+
 impl From<RedHatBoyState<Falling>> for RedHatBoyStateMachine {
     fn from(state: RedHatBoyState<Falling>) -> Self {
         RedHatBoyStateMachine::Falling(state)
@@ -436,6 +1083,42 @@ impl From<JumpingEndState> for RedHatBoyStateMachine {
     }
 }
 
+impl From<AirJumpEndState> for RedHatBoyStateMachine {
+    fn from(state: AirJumpEndState) -> Self {
+        match state {
+            AirJumpEndState::Jumping(jumping) => jumping.into(),
+            AirJumpEndState::DoubleJumping(double_jumping) => double_jumping.into(),
+        }
+    }
+}
+
+impl From<DoubleJumpingEndState> for RedHatBoyStateMachine {
+    fn from(state: DoubleJumpingEndState) -> Self {
+        match state {
+            DoubleJumpingEndState::DoubleJumping(double_jumping) => double_jumping.into(),
+            DoubleJumpingEndState::Landing(landing) => landing.into(),
+        }
+    }
+}
+
+impl From<HitEndState> for RedHatBoyStateMachine {
+    fn from(state: HitEndState) -> Self {
+        match state {
+            HitEndState::Hurt(hurt) => hurt.into(),
+            HitEndState::Falling(falling) => falling.into(),
+        }
+    }
+}
+
+impl From<HurtEndState> for RedHatBoyStateMachine {
+    fn from(state: HurtEndState) -> Self {
+        match state {
+            HurtEndState::Hurt(hurt) => hurt.into(),
+            HurtEndState::Running(running) => running.into(),
+        }
+    }
+}
+
 impl From<FallingEndState> for RedHatBoyStateMachine {
     fn from(state: FallingEndState) -> Self {
         match state {
@@ -446,31 +1129,42 @@ impl From<FallingEndState> for RedHatBoyStateMachine {
 }
 
 mod red_hat_boy_states {
-    use crate::engine::Point;
+    use crate::engine::{Animation, AnimationEvent, Clip, Playback, Point};
     //use super::HEIGHT;
-    use super::{Audio, Sound, HEIGHT};
+    use super::{GameConfig, HEIGHT};
     
-
-    //const FLOOR: i16 = 475;
-    const FLOOR: i16 = 479;
-    const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
-    const STARTING_POINT: i16 = -20;
-    const IDLE_FRAMES: u8 = 29;
-    const RUNNING_FRAMES: u8 = 23;
-    const JUMPING_FRAMES: u8 = 35;
-    const SLIDING_FRAMES: u8 = 14;
-    const FALLING_FRAMES: u8 = 29; // 10 'Dead' frames in the sheet, * 3 - 1.
     
     const IDLE_FRAME_NAME: &str = "Idle";
     const RUN_FRAME_NAME: &str = "Run";
     const SLIDING_FRAME_NAME: &str = "Slide";
     const JUMPING_FRAME_NAME: &str = "Jump";
     const FALLING_FRAME_NAME: &str = "Dead";
+    const HURT_FRAME_NAME: &str = "Hurt";
+    const DOUBLE_JUMP_CLIP: &str = "DoubleJump";
+
+    const TICKS_PER_FRAME: u16 = 3;
+    const DOUBLE_JUMP_TICKS_PER_FRAME: u16 = 2;
+
+    // Every animation the boy has, with the number of cells it has in rhb.json.
+    pub fn clips() -> Vec<Clip> {
+        vec![
+            Clip::numbered(IDLE_FRAME_NAME, 10, TICKS_PER_FRAME, Playback::Loop),
+            Clip::numbered(RUN_FRAME_NAME, 8, TICKS_PER_FRAME, Playback::Loop),
+            Clip::numbered(SLIDING_FRAME_NAME, 5, TICKS_PER_FRAME, Playback::Once),
+            Clip::numbered(JUMPING_FRAME_NAME, 12, TICKS_PER_FRAME, Playback::Loop),
+            Clip::numbered(FALLING_FRAME_NAME, 10, TICKS_PER_FRAME, Playback::Once),
+            Clip::numbered(HURT_FRAME_NAME, 8, TICKS_PER_FRAME, Playback::Once),
+            double_jump_clip(),
+        ]
+    }
+
+    // rhb.json has no frames of its own for it: the jump's, once through and
+    // quicker, while draw_params turns him a somersault.
+    fn double_jump_clip() -> Clip {
+        let jump = Clip::numbered(JUMPING_FRAME_NAME, 12, TICKS_PER_FRAME, Playback::Loop);
+        Clip::new(DOUBLE_JUMP_CLIP, jump.frames().to_vec(), DOUBLE_JUMP_TICKS_PER_FRAME, Playback::Once)
+    }
 
-    const RUNNING_SPEED: i16 = 3;    
-    const JUMP_SPEED: i16 = -27; //-25
-    const GRAVITY: i16 = 1;
-    const TERMINAL_VELOCITY: i16 = 18; //20;
 
 
     //#[derive(Copy, Clone)]
@@ -485,8 +1179,13 @@ mod red_hat_boy_states {
             &self.context
         }
 
-        fn update_context(&mut self, frames: u8) {
-            self.context = self.context.clone().update(frames);
+        pub fn context_mut(&mut self) -> &mut RedHatBoyContext {
+            &mut self.context
+        }
+
+        fn update_context(&mut self) -> Option<AnimationEvent> {
+            self.context = self.context.clone().update();
+            self.context.animation.update()
         }
     }
 
@@ -494,33 +1193,31 @@ mod red_hat_boy_states {
     pub struct Idle;
 
     impl RedHatBoyState<Idle> {
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
+        pub fn new(animation: Animation, config: GameConfig) -> Self {
 
             RedHatBoyState {
                 context: RedHatBoyContext {
-                    frame: 0,
-                    position: Point { x: STARTING_POINT, y: FLOOR, },
+                    animation,
+                    position: Point { x: config.starting_point, y: config.floor, },
                     velocity: Point { x: 0, y: 0 },
 
-                    audio,
-                    jump_sound,
+                    config,
+                    lives: config.lives,
+                    invulnerable: 0,
+                    air_jumps: 0,
                 },
                 _state: Idle {},
             }
         }
 
-        pub fn frame_name(&self) -> &str {
-            IDLE_FRAME_NAME
-        }
-
         pub fn update(mut self) -> RedHatBoyState<Idle> {
-            self.update_context(IDLE_FRAMES);
+            self.update_context();
             self
         }
 
         pub fn run(self) -> RedHatBoyState<Running> {
             RedHatBoyState {
-                context: self.context.reset_frame().run_right(),
+                context: self.context.play(RUN_FRAME_NAME).run_right(),
                 _state: Running {},
             }
         }
@@ -530,36 +1227,31 @@ mod red_hat_boy_states {
     pub struct Running;
 
     impl RedHatBoyState<Running> {
-        pub fn frame_name(&self) -> &str {
-            RUN_FRAME_NAME
-        }
-
         pub fn update(mut self) -> RedHatBoyState<Running> {
-            self.update_context(RUNNING_FRAMES);
+            self.update_context();
             self
         }
 
         pub fn jump(self) -> RedHatBoyState<Jumping> {
+            let jump_speed = self.context.config.jump_speed;
             RedHatBoyState {
                 context: self
                 .context
-                .reset_frame()
-                .set_vertical_velocity(JUMP_SPEED)
-                .play_jump_sound(),
+                .play(JUMPING_FRAME_NAME)
+                .set_vertical_velocity(jump_speed)
+                .refill_air_jumps(),
                 _state: Jumping {},
             }
         }        
 
         pub fn slide(self) -> RedHatBoyState<Sliding> {
             RedHatBoyState {
-                context: self.context.reset_frame(),
+                context: self.context.play(SLIDING_FRAME_NAME),
                 _state: Sliding {},
             }
         }
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState { context: self.context.reset_frame().stop(),
-                             _state: Falling {},
-            }
+        pub fn knock_out(self) -> HitEndState {
+            hit(self.context)
         }
         
         pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
@@ -579,15 +1271,11 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Jumping> {
-        pub fn frame_name(&self) -> &str {
-            JUMPING_FRAME_NAME
-        }
-
         pub fn update(mut self) -> JumpingEndState {
-            self.update_context(JUMPING_FRAMES);
+            self.update_context();
 
-            if self.context.position.y >= FLOOR {
-                JumpingEndState::Landing(self.land_on(HEIGHT.into()))
+            if self.context.position.y >= self.context.config.floor {
+                JumpingEndState::Landing(self.land_on(HEIGHT))
             } else {
                 JumpingEndState::Jumping(self)
             }
@@ -595,20 +1283,99 @@ mod red_hat_boy_states {
 
         pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
                 RedHatBoyState {
-                    context: self.context.reset_frame().set_on(position),
+                    context: self.context.play(RUN_FRAME_NAME).set_on(position).run_at_running_speed(),
                     _state: Running,
                 }
             
         }//^-- fn land_on
 
+        pub fn air_jump(self) -> AirJumpEndState {
+            if self.context.air_jumps > 0 {
+                AirJumpEndState::DoubleJumping(air_jump(self.context))
+            } else {
+                AirJumpEndState::Jumping(self)
+            }
+        }
 
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState { context: self.context.reset_frame().stop(),
-                             _state: Falling {},
+        pub fn steer(self, direction: i16) -> RedHatBoyState<Jumping> {
+            RedHatBoyState {
+                context: self.context.steer(direction),
+                _state: Jumping,
             }
         }
+
+        pub fn knock_out(self) -> HitEndState {
+            hit(self.context)
+        }
+    }
+
+    // What AirJump does: another jump while he has air jumps left.
+    pub enum AirJumpEndState {
+        Jumping(RedHatBoyState<Jumping>),
+        DoubleJumping(RedHatBoyState<DoubleJumping>),
+    }
+
+    fn air_jump(context: RedHatBoyContext) -> RedHatBoyState<DoubleJumping> {
+        let double_jump_speed = context.config.double_jump_speed;
+        RedHatBoyState {
+            context: context
+                        .play(DOUBLE_JUMP_CLIP)
+                        .set_vertical_velocity(double_jump_speed)
+                        .spend_air_jump(),
+            _state: DoubleJumping,
+        }
+    }
+
+    /*
+    Jumped again before landing. Everything Jumping does, except the
+    clip; further air jumps keep him here.
+    */
+    #[derive(Copy, Clone)]
+    pub struct DoubleJumping;
+
+    pub enum DoubleJumpingEndState {
+        DoubleJumping(RedHatBoyState<DoubleJumping>),
+        Landing(RedHatBoyState<Running>),
     }
 
+    impl RedHatBoyState<DoubleJumping> {
+        pub fn update(mut self) -> DoubleJumpingEndState {
+            self.update_context();
+
+            if self.context.position.y >= self.context.config.floor {
+                DoubleJumpingEndState::Landing(self.land_on(HEIGHT))
+            } else {
+                DoubleJumpingEndState::DoubleJumping(self)
+            }
+        }
+
+        pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.play(RUN_FRAME_NAME).set_on(position).run_at_running_speed(),
+                _state: Running,
+            }
+        }
+
+        pub fn air_jump(self) -> AirJumpEndState {
+            if self.context.air_jumps > 0 {
+                AirJumpEndState::DoubleJumping(air_jump(self.context))
+            } else {
+                AirJumpEndState::DoubleJumping(self)
+            }
+        }
+
+        pub fn steer(self, direction: i16) -> RedHatBoyState<DoubleJumping> {
+            RedHatBoyState {
+                context: self.context.steer(direction),
+                _state: DoubleJumping,
+            }
+        }
+
+        pub fn knock_out(self) -> HitEndState {
+            hit(self.context)
+        }
+    }//^-- impl RedHatBoyState<DoubleJumping>
+
     #[derive(Copy, Clone)]
     pub struct Sliding;
 
@@ -618,31 +1385,28 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Sliding> {
-        pub fn frame_name(&self) -> &str {
-            SLIDING_FRAME_NAME
+        // Held down, the slide lasts as long as it's held.
+        pub fn keep_sliding(mut self) -> RedHatBoyState<Sliding> {
+            self.context.animation.hold();
+            self
         }
 
         pub fn update(mut self) -> SlidingEndState {
-            self.update_context(SLIDING_FRAMES);
-
-            if self.context.frame >= SLIDING_FRAMES {
-                SlidingEndState::Running(self.stand())
-            } else {
-                SlidingEndState::Sliding(self)
+            match self.update_context() {
+                Some(AnimationEvent::Finished) => SlidingEndState::Running(self.stand()),
+                _ => SlidingEndState::Sliding(self),
             }
         }
 
         pub fn stand(self) -> RedHatBoyState<Running> {
             RedHatBoyState {
-                context: self.context.reset_frame(),
+                context: self.context.play(RUN_FRAME_NAME),
                 _state: Running {},
             }
         }
 
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState { context: self.context.reset_frame().stop(),
-                             _state: Falling {},
-            }
+        pub fn knock_out(self) -> HitEndState {
+            hit(self.context)
         }
 
         pub fn land_on(self, position: i16) -> RedHatBoyState<Sliding> {
@@ -653,15 +1417,75 @@ mod red_hat_boy_states {
         }
     }
 
-   #[derive(Copy, Clone)]
-   pub struct KnockedOut;
+    // What a hit does: costs a life if he has one to spare, otherwise he's down.
+    pub enum HitEndState {
+        Hurt(RedHatBoyState<Hurt>),
+        Falling(RedHatBoyState<Falling>),
+    }
 
-   impl RedHatBoyState<KnockedOut> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+    fn hit(context: RedHatBoyContext) -> HitEndState {
+        if context.lives > 1 {
+            HitEndState::Hurt(RedHatBoyState {
+                context: context.play(HURT_FRAME_NAME).lose_life(),
+                _state: Hurt,
+            })
+        } else {
+            HitEndState::Falling(RedHatBoyState {
+                context: context.play(FALLING_FRAME_NAME).stop(),
+                _state: Falling {},
+            })
         }
     }
 
+    /*
+    Hit with a life to spare. He keeps going while the Hurt clip plays,
+    can't jump or slide until it's over, and is invulnerable for
+    invulnerable_ticks, which can be shorter than the clip; see
+    RedHatBoyContext::invulnerable.
+    */
+    #[derive(Copy, Clone)]
+    pub struct Hurt;
+
+    pub enum HurtEndState {
+        Hurt(RedHatBoyState<Hurt>),
+        Running(RedHatBoyState<Running>),
+    }
+
+    impl RedHatBoyState<Hurt> {
+        pub fn update(mut self) -> HurtEndState {
+            match self.update_context() {
+                Some(AnimationEvent::Finished) => HurtEndState::Running(self.recover()),
+                _ => HurtEndState::Hurt(self),
+            }
+        }
+
+        pub fn recover(self) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.play(RUN_FRAME_NAME).run_at_running_speed(),
+                _state: Running {},
+            }
+        }
+
+        // Only counts once he's no longer invulnerable, then it's a hit like any other.
+        pub fn knock_out(self) -> HitEndState {
+            if self.context.invulnerable > 0 {
+                HitEndState::Hurt(self)
+            } else {
+                hit(self.context)
+            }
+        }
+
+        pub fn land_on(self, position: i16) -> RedHatBoyState<Hurt> {
+            RedHatBoyState {
+                context: self.context.set_on(position),
+                _state: Hurt,
+            }
+        }
+    }//^-- impl RedHatBoyState<Hurt>
+
+   #[derive(Copy, Clone)]
+   pub struct KnockedOut;
+
     #[derive(Copy, Clone)]
     pub struct Falling;
 
@@ -672,10 +1496,6 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Falling> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
-        }
-
         pub fn knock_out(self) -> RedHatBoyState<KnockedOut> {
             RedHatBoyState {
                 context: self.context,
@@ -684,11 +1504,9 @@ mod red_hat_boy_states {
         }
         
         pub fn update(mut self) -> FallingEndState {
-            self.update_context(FALLING_FRAMES);
-            if self.context.frame >= FALLING_FRAMES {
-                FallingEndState::KnockedOut(self.knock_out())
-            } else {
-                FallingEndState::Falling(self)
+            match self.update_context() {
+                Some(AnimationEvent::Finished) => FallingEndState::KnockedOut(self.knock_out()),
+                _ => FallingEndState::Falling(self),
             }
         }
  
@@ -699,36 +1517,39 @@ mod red_hat_boy_states {
     //#[derive(Copy, Clone)]
     #[derive(Clone)]
     pub struct RedHatBoyContext {
-        pub frame: u8,
+        pub animation: Animation,
         pub position: Point,
         pub velocity: Point,
-        pub audio: Audio,
-        pub jump_sound: Sound,
+        pub config: GameConfig,
+        pub lives: i16,
+        // Ticks left during which hits are ignored.
+        pub invulnerable: i16,
+        // Jumps left before he lands again.
+        pub air_jumps: i16,
     }
 
     impl RedHatBoyContext {
-        pub fn update(mut self, frame_count: u8) -> Self {
-            if self.velocity.y < TERMINAL_VELOCITY {
-                self.velocity.y += GRAVITY;
-            }
-
-            if self.frame < frame_count {
-                self.frame += 1;
-            } else {
-                self.frame = 0;
+        pub fn update(mut self) -> Self {
+            if self.velocity.y < self.config.terminal_velocity {
+                self.velocity.y += self.config.gravity;
             }
             
+            self.position.x += self.velocity.x;
             self.position.y += self.velocity.y;
 
-            if self.position.y > FLOOR {
-                self.position.y = FLOOR;
+            if self.position.y > self.config.floor {
+                self.position.y = self.config.floor;
+            }
+
+            if self.invulnerable > 0 {
+                self.invulnerable -= 1;
             }
 
             self
         }
 
-        fn reset_frame(mut self) -> Self {
-            self.frame = 0;
+        fn play(mut self, clip: &str) -> Self {
+            self.animation.play(clip);
             self
         }
 
@@ -737,8 +1558,43 @@ mod red_hat_boy_states {
             self
         }
 
-        fn run_right(mut self) -> Self {
-            self.velocity.x += RUNNING_SPEED;
+        fn run_right(mut self) -> Self {
+            self.velocity.x += self.config.running_speed;
+            self
+        }
+
+        fn run_at_running_speed(mut self) -> Self {
+            self.velocity.x = self.config.running_speed;
+            self
+        }
+
+        fn refill_air_jumps(mut self) -> Self {
+            self.air_jumps = self.config.air_jumps;
+            self
+        }
+
+        fn spend_air_jump(mut self) -> Self {
+            self.air_jumps -= 1;
+            self
+        }
+
+        /*
+        Nudges his speed by air_control a tick, within the air speed limits.
+        Without air control his speed is left alone, even outside them.
+        */
+        fn steer(mut self, direction: i16) -> Self {
+            if self.config.air_control == 0 {
+                return self;
+            }
+
+            let nudged = self.velocity.x + direction.signum() * self.config.air_control;
+            self.velocity.x = nudged.clamp(self.config.air_speed_min, self.config.air_speed_max);
+            self
+        }
+
+        fn lose_life(mut self) -> Self {
+            self.lives -= 1;
+            self.invulnerable = self.config.invulnerable_ticks;
             self
         }
 
@@ -748,17 +1604,17 @@ mod red_hat_boy_states {
         }
 
         fn set_on(mut self, position: i16) -> Self {
-            let position = position - PLAYER_HEIGHT;
+            let position = position - (HEIGHT - self.config.floor);
             self.position.y = position;
             self
         }
 
-        fn play_jump_sound(self) -> Self {
-            if let Err(err) = self.audio.play_sound(&self.jump_sound) {
-                log!("Error playing jump sound {:#?}", err);
+        // Takes effect straight away, even mid run.
+        pub fn set_config(&mut self, config: GameConfig) {
+            if self.velocity.x != 0 {
+                self.velocity.x = config.running_speed;
             }
-
-            self
+            self.config = config;
         }
     }//^-- impl RedHatBoyContext
 }//^-- mod
@@ -766,69 +1622,299 @@ mod red_hat_boy_states {
 pub struct Walk {
     obstacle_sheet: Rc<SpriteSheet>,
     boy: RedHatBoy,
-    backgrounds: [Image; 2],
+    background: ParallaxBackground,
     obstacles: Vec<Box<dyn Obstacle>>,
-    stone: HtmlImageElement,
+    // Finds the obstacles near the boy, rebuilt when obstacles_moved.
+    grid: SpatialGrid,
+    // Kept between ticks so collisions don't allocate, see touch_obstacles.
+    nearby: Vec<usize>,
+    collisions: Vec<CollisionEvent>,
+    obstacles_moved: bool,
+    stone: Stone,
     timeline: i16,
+    camera: Camera,
+    particles: ParticleSystem,
+    dust: BoyParticles,
+    dust_trail: Option<EmitterId>,
+    config: GameConfig,
+    score: u32,
+    audio: Audio,
+    collect_sound: Option<Sound>,
+}
+
+// The boy's particle effects, registered with the Walk's ParticleSystem.
+#[derive(Clone, Copy)]
+struct BoyParticles {
+    landing: EffectId,
+    slide: EffectId,
+    impact: EffectId,
+    sparkle: EffectId,
 }
 
+impl BoyParticles {
+    fn register(particles: &mut ParticleSystem) -> Self {
+        let dust = ParticleEffect {
+            min_velocity: (-2.0, -2.0),
+            max_velocity: (2.0, -0.5),
+            gravity: 0.15,
+            lifetime: 20,
+            size: 4,
+            color: "#C8B491",
+            fade: true,
+            sprite: None,
+        };
+
+        BoyParticles {
+            landing: particles.add_effect(dust.clone()),
+            slide: particles.add_effect(ParticleEffect {
+                min_velocity: (-1.5, -1.0),
+                max_velocity: (-0.5, 0.0),
+                gravity: 0.05,
+                lifetime: 15,
+                size: 3,
+                ..dust.clone()
+            }),
+            impact: particles.add_effect(ParticleEffect {
+                min_velocity: (-3.0, -4.0),
+                max_velocity: (3.0, -1.0),
+                gravity: 0.3,
+                lifetime: 30,
+                size: 5,
+                color: "#F4F4F4",
+                ..dust.clone()
+            }),
+            sparkle: particles.add_effect(ParticleEffect {
+                min_velocity: (-2.0, -2.0),
+                max_velocity: (2.0, 2.0),
+                gravity: 0.0,
+                lifetime: 18,
+                size: 3,
+                color: "#F1C40F",
+                ..dust
+            }),
+        }
+    }
+}//^-- impl BoyParticles
+
 impl Walk {
     fn reset(walk: Self) -> Self {
         let starting_obstacles = stone_and_platform(
                                     walk.stone.clone(),
                                     walk.obstacle_sheet.clone(), 0);
         let timeline = rightmost(&starting_obstacles);
+        let mut background = walk.background;
+        background.reset();
+        let mut particles = walk.particles;
+        particles.clear();
 
         Walk {
             boy: RedHatBoy::reset(walk.boy), //walk.boy,
-            backgrounds: walk.backgrounds,
+            background,
             obstacles: starting_obstacles,
+            grid: SpatialGrid::new(OBSTACLE_GRID_COLUMN),
+            nearby: vec![],
+            collisions: vec![],
+            obstacles_moved: true,
             obstacle_sheet: walk.obstacle_sheet,
             stone: walk.stone,
             timeline,
+            camera: Camera::new(WIDTH, HEIGHT),
+            particles,
+            dust: walk.dust,
+            dust_trail: None,
+            config: walk.config,
+            score: 0,
+            audio: walk.audio,
+            collect_sound: walk.collect_sound,
         }
     }
 
     fn knocked_out(&self) -> bool {
         self.boy.knocked_out()
     }
-    fn velocity(&self) -> i16 {
-        -self.boy.walking_speed()
+
+    fn set_config(&mut self, config: GameConfig) {
+        self.config = config;
+        self.boy.set_config(config);
+    }
+
+    // See touch_obstacles for the rules; this applies what they came to.
+    fn resolve_collisions(&mut self) {
+        if self.obstacles_moved {
+            let bounds: Vec<Rect> = self.obstacles.iter().map(|obstacle| obstacle.bounds()).collect();
+            self.grid.rebuild(&bounds);
+            self.obstacles_moved = false;
+        }
+
+        let contacts = touch_obstacles(
+            &self.boy.bounding_box(),
+            self.boy.velocity(),
+            self.boy.invulnerable(),
+            &mut self.obstacles,
+            &self.grid,
+            &mut self.nearby,
+            &mut self.collisions,
+        );
+
+        for &(value, centre) in contacts.collected.iter() {
+            self.collect(value, centre);
+        }
+
+        self.boy.touch(&contacts);
+
+        if contacts.removed {
+            self.obstacles_moved = true;
+        }
+    }//^-- fn resolve_collisions
+
+    fn collect(&mut self, value: u32, centre: Point) {
+        self.score += value;
+        self.particles.burst(self.dust.sparkle, centre, 10);
+
+        if let Some(sound) = &self.collect_sound {
+            if let Err(err) = self.audio.play_sound(sound) {
+                log!("Error playing collect sound {:#?}", err);
+            }
+        }
+    }
+
+    /*
+    Moves everything, camera included, back by where the camera is,
+    so nothing on screen changes but the coordinates stay small.
+    */
+    fn shift_origin(&mut self) {
+        let distance = -self.camera.left();
+        self.camera.move_horizontally(distance);
+        self.boy.move_horizontally(distance);
+        self.particles.move_horizontally(distance);
+        self.obstacles
+            .iter_mut()
+            .for_each(|obstacle| obstacle.move_horizontally(distance));
+        self.obstacles_moved = true;
+        self.timeline += distance;
     }
 
     fn generate_next_segment(&mut self) {
         let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..2);
+        let next_segment = rng.gen_range(0..4);
 
         let mut next_obstacles = match next_segment {
             0 => stone_and_platform(
                 self.stone.clone(),
                 self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
+                self.timeline + self.config.obstacle_buffer,
             ),
             1 => platform_and_stone(
                 self.stone.clone(),
                 self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
+                self.timeline + self.config.obstacle_buffer,
+            ),
+            2 => moving_platforms(
+                self.stone.clone(),
+                self.obstacle_sheet.clone(),
+                self.timeline + self.config.obstacle_buffer,
+            ),
+            3 => slide_under(
+                self.stone.clone(),
+                self.obstacle_sheet.clone(),
+                self.timeline + self.config.obstacle_buffer,
             ),
             _ =>vec![],
         };
 
         self.timeline = rightmost(&next_obstacles);
         self.obstacles.append(&mut next_obstacles);
+        self.obstacles_moved = true;
 
     }//^-- fn generate_next_segment
 
-    fn draw(&self, renderer: &Renderer) {
-        self.backgrounds.iter().for_each(|background| { background.draw(renderer); });
-        self.boy.draw(renderer);
-        self.obstacles.iter().for_each(|obstacle| { obstacle.draw(renderer); });
+    // Turns what happened to the boy this tick into particles.
+    fn emit_particles(&mut self) {
+        let feet = self.boy.feet();
+        for effect in self.boy.take_effects() {
+            match effect {
+                BoyEffect::Jumped => (),
+                BoyEffect::DoubleJumped => self.particles.burst(self.dust.landing, feet, 6),
+                BoyEffect::Landed => self.particles.burst(self.dust.landing, feet, 8),
+                BoyEffect::KnockedDown => self.particles.burst(self.dust.impact, feet, 16),
+                BoyEffect::Hurt => self.particles.burst(self.dust.impact, feet, 8),
+                BoyEffect::SlideStarted => {
+                    self.dust_trail = Some(self.particles.start(self.dust.slide, feet, 0.5));
+                }
+                BoyEffect::SlideEnded => {
+                    if let Some(trail) = self.dust_trail.take() {
+                        self.particles.stop(trail);
+                    }
+                }
+            }
+        }
+
+        if let Some(trail) = self.dust_trail {
+            self.particles.move_emitter(trail, feet);
+        }
+        self.particles.update();
+    }//^-- fn emit_particles
+
+    // A square for each life under the score, when there's more than one to lose.
+    fn draw_lives(&self, queue: &mut RenderQueue) {
+        const LIFE_SIZE: i16 = 16;
+        const LIFE_GAP: i16 = 6;
+        const LIFE_COLOR: &str = "#E74C3C";
+        if self.config.lives <= 1 {
+            return;
+        }
+
+        for life in 0..self.boy.lives() {
+            queue.fill_rect(
+                Layer::Hud,
+                &Rect::new_from_x_y(470 + life * (LIFE_SIZE + LIFE_GAP), 52, LIFE_SIZE, LIFE_SIZE),
+                LIFE_COLOR,
+            );
+        }
+    }
+
+    // Collision boxes in the world and what the boy is up to in the corner.
+    #[cfg(feature = "debug-overlay")]
+    fn draw_debug(&self, queue: &mut RenderQueue) {
+        queue.with_camera(&self.camera, |queue| {
+            self.obstacles.iter().for_each(|obstacle| obstacle.draw_rect(queue));
+            self.boy.draw_rect(queue);
+        });
+
+        let context = self.boy.state_machine.context();
+        let lines = [
+            format!("State {}", self.boy.state_machine.name()),
+            format!("Frame {}", self.boy.animation().frame_name().unwrap_or("none")),
+            format!("Velocity {}, {}", context.velocity.x, context.velocity.y),
+            format!("Timeline {}", self.timeline),
+            format!("Obstacles {}", self.obstacles.len()),
+        ];
+        queue.fill_rect_with_alpha(Layer::Hud, &Rect::new_from_x_y(10, 10, 280, 125), "#FFFFFF", 0.7);
+        for (line, text) in lines.iter().enumerate() {
+            queue.draw_text(Layer::Hud, text, &Point { x: 20, y: 34 + 23 * line as i16 });
+        }
+    }//^-- fn draw_debug
+
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()> {
+        queue.draw_text(Layer::Hud, format_args!("Score {}", self.score), &Point { x: 470, y: 40 });
+        self.draw_lives(queue);
+        self.background.draw(queue, self.camera.width());
+        queue.with_camera(&self.camera, |queue| {
+            self.obstacles.iter().for_each(|obstacle| obstacle.draw(queue));
+            self.particles.draw(queue);
+            self.boy.draw(queue)
+        })
     }
 
 }
 
 pub struct WalkTheDog {
     machine: Option<WalkTheDogStateMachine>,
+    // New configs from config::watch, debug builds only.
+    config_updates: Option<UnboundedReceiver<GameConfig>>,
+    // F3 toggles the debug overlay.
+    #[cfg(feature = "debug-overlay")]
+    show_debug: bool,
 }
 
 enum WalkTheDogStateMachine {
@@ -837,6 +1923,62 @@ enum WalkTheDogStateMachine {
     GameOver(WalkTheDogState<GameOver>),
 }
 
+/*
+Every move is on update, decided by Stage::next; the events are the
+Inputs it decides on. Only the tests and the diagram tool read it.
+*/
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub const WALK_THE_DOG_TRANSITIONS: TransitionTable = TransitionTable {
+    name: "WalkTheDogStateMachine",
+    initial: "Ready",
+    transitions: &[
+        ("Ready",    "ArrowRight", &["Walking"]),
+        ("Walking",  "KnockedOut", &["GameOver"]),
+        ("GameOver", "NewGame",    &["Ready"]),
+    ],
+};
+
+// Which WalkTheDogStateMachine state, without the Walk it holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Ready,
+    Walking,
+    GameOver,
+}
+
+// What a tick's update can move on, one for each WALK_THE_DOG_TRANSITIONS event.
+#[derive(Clone, Copy, Debug, Default)]
+struct Inputs {
+    arrow_right: bool,
+    knocked_out: bool,
+    new_game: bool,
+}
+
+impl Stage {
+    /*
+    Where the game goes from here. Each state's update works out the
+    Inputs and follows this, so the transitions can be checked against
+    their table without a browser.
+    */
+    fn next(self, inputs: Inputs) -> Stage {
+        match self {
+            Stage::Ready if inputs.arrow_right => Stage::Walking,
+            Stage::Walking if inputs.knocked_out => Stage::GameOver,
+            Stage::GameOver if inputs.new_game => Stage::Ready,
+            _ => self,
+        }
+    }
+
+    #[cfg(test)]
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Ready => "Ready",
+            Stage::Walking => "Walking",
+            Stage::GameOver => "GameOver",
+        }
+    }
+}//^-- impl Stage
+
 impl WalkTheDogStateMachine {
     fn new(walk: Walk) -> Self {
         WalkTheDogStateMachine::Ready(WalkTheDogState::new(walk))
@@ -847,14 +1989,31 @@ impl WalkTheDogStateMachine {
         match self {
             WalkTheDogStateMachine::Ready(state) => state.update(keystate).into(),
             WalkTheDogStateMachine::Walking(state) => state.update(keystate).into(),
-            WalkTheDogStateMachine::GameOver(state) => state.update().into(),
+            WalkTheDogStateMachine::GameOver(state) => state.update(keystate).into(),
+        }
+    }
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()> {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => state.draw(queue),
+            WalkTheDogStateMachine::Walking(state) => state.draw(queue),
+            WalkTheDogStateMachine::GameOver(state) => state.draw(queue),
+        }
+    }
+
+    fn walk_mut(&mut self) -> &mut Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &mut state.walk,
+            WalkTheDogStateMachine::Walking(state) => &mut state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &mut state.walk,
         }
     }
-    fn draw(&self, renderer: &Renderer) {
+
+    #[cfg(feature = "debug-overlay")]
+    fn walk(&self) -> &Walk {
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.draw(renderer),
-            WalkTheDogStateMachine::Walking(state) => state.draw(renderer),
-            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer),
+            WalkTheDogStateMachine::Ready(state) => &state.walk,
+            WalkTheDogStateMachine::Walking(state) => &state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &state.walk,
         }
     }
 }
@@ -866,8 +2025,8 @@ struct WalkTheDogState<T> {
 
 
 impl<T> WalkTheDogState<T> {
-    fn draw(&self, renderer: &Renderer) {
-        self.walk.draw(renderer);
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()> {
+        self.walk.draw(queue)
     }
 }
 
@@ -880,11 +2039,14 @@ struct GameOver {
 }
 
 impl GameOver {
-    fn new_game_pressed(&mut self) -> bool {
-        matches!(self.new_game_event.try_next(), Ok(Some(())))
+    // The button, or Enter for when the button couldn't be shown.
+    fn new_game_pressed(&mut self, keystate: &KeyState) -> bool {
+        matches!(self.new_game_event.try_next(), Ok(Some(()))) || keystate.was_pressed("Enter")
     }
 }
 
+// Both variants hold the same Walk, clippy can't see through the generic.
+#[allow(clippy::large_enum_variant)]
 enum GameOverEndState {
     Continue(WalkTheDogState<GameOver>),
     Complete(WalkTheDogState<Ready>),
@@ -923,10 +2085,10 @@ impl WalkTheDogState<Ready> {
 
     fn update(mut self, keystate: &KeyState) -> ReadyEndState {
         self.walk.boy.update();
-        if keystate.is_pressed("ArrowRight") {
-            ReadyEndState::Complete(self.start_running())
-        } else {
-            ReadyEndState::Continue(self)
+        let inputs = Inputs { arrow_right: keystate.is_pressed("ArrowRight"), ..Inputs::default() };
+        match Stage::Ready.next(inputs) {
+            Stage::Walking => ReadyEndState::Complete(self.start_running()),
+            _ => ReadyEndState::Continue(self),
         }
     }
 }
@@ -945,8 +2107,12 @@ impl WalkTheDogState<Walking> {
     fn end_game(self) -> WalkTheDogState<GameOver> {
         let receiver = browser::draw_ui("<button id='new_game'>New Game</button>")
                             .and_then(|_unit| browser::find_html_element_by_id("new_game"))
-                            .map(|element| engine::add_click_handler(element))
-                            .unwrap();
+                            .map(engine::add_click_handler)
+                            .unwrap_or_else(|err| {
+                                // Enter still starts a new game, see new_game_pressed.
+                                error!("Could not show the New Game button, press Enter instead {:#?}", err);
+                                unbounded().1
+                            });
 
         WalkTheDogState {
             //_state: GameOver,
@@ -956,50 +2122,67 @@ impl WalkTheDogState<Walking> {
     }
 
     fn update(mut self, keystate: &KeyState) -> WalkingEndState {
+        /*
+        Once per press in the air, or holding it would spend the air jumps
+        straight away. Before jump, so the press that leaves the ground
+        isn't also the first air jump.
+        */
+        if keystate.was_pressed("Space") {
+            self.walk.boy.air_jump();
+        }
+
         if keystate.is_pressed("Space") {
             self.walk.boy.jump();
         }
 
+        if keystate.is_pressed("ArrowLeft") {
+            self.walk.boy.steer(-1);
+        }
+
+        if keystate.is_pressed("ArrowRight") {
+            self.walk.boy.steer(1);
+        }
+
         if keystate.is_pressed("ArrowDown") {
             self.walk.boy.slide();
         }
 
+        self.walk.obstacles.iter_mut().for_each(|obstacle| obstacle.update());
         self.walk.boy.update();
 
-        let walking_speed = self.walk.velocity();
-        let [first_background, second_background] = &mut self.walk.backgrounds;
-        first_background.move_horizontally(walking_speed);
-        second_background.move_horizontally(walking_speed);
+        let previous_left = self.walk.camera.left();
+        self.walk.camera.follow(self.walk.boy.position(), self.walk.config.starting_point);
+        let camera_left = self.walk.camera.left();
+        self.walk.background.scroll(camera_left - previous_left);
 
-        if first_background.right() < 0 {
-            first_background.set_x(second_background.right());
-        }
-        if second_background.right() < 0 {
-            second_background.set_x(first_background.right());
+        let count = self.walk.obstacles.len();
+        self.walk.obstacles.retain(|obstacle| obstacle.right() > camera_left);
+        if self.walk.obstacles.len() != count {
+            self.walk.obstacles_moved = true;
         }
+        self.walk.resolve_collisions();
 
-        self.walk.obstacles.retain(|obstacle| obstacle.right() > 0);
-
-        self.walk.obstacles.iter_mut().for_each(|obstacle| {
-            obstacle.move_horizontally(walking_speed);
-            obstacle.check_intersection(&mut self.walk.boy);
-        });
+        self.walk.emit_particles();
 
-        if self.walk.timeline < TIMELINE_MINIMUM {
+        if self.walk.timeline < camera_left + self.walk.config.timeline_minimum {
             self.walk.generate_next_segment();
-        } else {
-            self.walk.timeline += walking_speed;
+        }
+
+        if camera_left > ORIGIN_LIMIT {
+            self.walk.shift_origin();
         }
 
         //self
-        if self.walk.knocked_out() {
-            WalkingEndState::Complete(self.end_game())
-        } else {
-            WalkingEndState::Continue(self)
+        let inputs = Inputs { knocked_out: self.walk.knocked_out(), ..Inputs::default() };
+        match Stage::Walking.next(inputs) {
+            Stage::GameOver => WalkingEndState::Complete(self.end_game()),
+            _ => WalkingEndState::Continue(self),
         }
     }//^-- fn update
 }//^-- impl WalkTheDogState<Walking> 
 
+// Both variants hold the same Walk, clippy can't see through the generic.
+#[allow(clippy::large_enum_variant)]
 enum WalkingEndState {
     Continue(WalkTheDogState<Walking>),
     Complete(WalkTheDogState<GameOver>),
@@ -1022,11 +2205,11 @@ impl WalkTheDogState<GameOver> {
         self
     }
 */
-    fn update(mut self) -> GameOverEndState {
-        if self._state.new_game_pressed() {
-            GameOverEndState::Complete(self.new_game())
-        } else {
-            GameOverEndState::Continue(self)
+    fn update(mut self, keystate: &KeyState) -> GameOverEndState {
+        let inputs = Inputs { new_game: self._state.new_game_pressed(keystate), ..Inputs::default() };
+        match Stage::GameOver.next(inputs) {
+            Stage::Ready => GameOverEndState::Complete(self.new_game()),
+            _ => GameOverEndState::Continue(self),
         }
     }
 
@@ -1077,7 +2260,12 @@ impl From<ReadyEndState> for WalkTheDogStateMachine {
 
 impl WalkTheDog {
     pub fn new() -> Self {
-        WalkTheDog { machine: None }
+        WalkTheDog {
+            machine: None,
+            config_updates: None,
+            #[cfg(feature = "debug-overlay")]
+            show_debug: false,
+        }
     }
 }
 
@@ -1086,41 +2274,50 @@ impl Game for WalkTheDog {
     async fn initialize(&self) -> Result<Box<dyn Game>> {
         match self.machine {
             None => {         
-                
-                //serde
-                //let sheet = browser::fetch_json("../resources/pix/rhb.json").await?.into_serde()?;    
-                
-                //serde_wasm_bindgen    
-                //let sheet = serde_wasm_bindgen::from_value(
-                //                browser::fetch_json("../resources/pix/rhb.json").await?).unwrap();
-                //gloo_utils                
-                let sheet = JsValueSerdeExt::into_serde(&browser::fetch_json("../resources/pix/rhb.json").await?)?;
-                
-                let audio = Audio::new()?;
-                let sound = audio.load_sound("../resources/sound/SFX_Jump_23.mp3").await?;
-                let _background_music = audio.load_sound("../resources/sound/background_song.mp3").await?;
+                let loading_screen = engine::LoadingScreen::new()?;
+                let manifest = AssetManifest::fetch(ASSET_MANIFEST).await?;
+                let config = GameConfig::load(config::GAME_CONFIG).await;
+                let audio = Audio::new();
+                let assets = engine::load_assets(&manifest, &audio, |progress| {
+                                    loading_screen.draw(progress)
+                             }).await?;
+
+                // Only missing when there's no audio; a required sound that
+                // fails to load stops load_assets.
+                let sound = assets.optional_sound("jump");
+                let double_jump_sound = assets.optional_sound("double_jump");
+                // Optional: without it the game just runs in silence.
+                let _background_music = assets.optional_sound("background_music");
 
                 //play it immediately and drive people nuts                
                 //audio.play_looping_sound(&_background_music)?;
 
-                let rhb = RedHatBoy::new(sheet, 
-                                         engine::load_image("../resources/pix/rhb.png").await?,
-                                         audio,
-                                         sound,);
-
-                let background = engine::load_image("../resources/pix/BG.png").await?;
-                let stone = engine::load_image("../resources/pix/Stone.png").await?;
-                let tiles = browser::fetch_json("../resources/pix/tiles.json").await?;
-                let sprite_sheet = Rc::new(
-                                    SpriteSheet::new(
-                                        //tiles.into_serde::<Sheet>()?,
-                                        //serde_wasm_bindgen::from_value(tiles)?,
-                                        JsValueSerdeExt::into_serde(&tiles)?,
-                                        engine::load_image("../resources/pix/tiles.png").await?,
-                                   ));
-
-                let background_width = background.width() as i16;
-                
+                let rhb_sheet = assets.sprite_sheet("rhb")?;
+                let collect_sound = assets.optional_sound("collect");
+                let rhb = RedHatBoy::new(rhb_sheet.clone(),
+                                         audio.clone(),
+                                         sound,
+                                         double_jump_sound,
+                                         config);
+
+                let layers = BACKGROUND_LAYERS
+                                .iter()
+                                .map(|&(name, scroll_factor, y)| {
+                                    assets.image(name)
+                                          .map(|image| ParallaxLayer::new(image, scroll_factor, y, true))
+                                })
+                                .collect::<Result<Vec<_>>>()?;
+                let stone = Stone {
+                    image: assets.image("stone")?,
+                    hitboxes: manifest.hitboxes("stone").to_vec(),
+                };
+                let sprite_sheet = assets.sprite_sheet("tiles")?;
+
+                check_sheets(rhb_sheet.sheet(), sprite_sheet.sheet())
+                    .iter()
+                    .filter(|report| !report.is_ok())
+                    .for_each(|report| { error!("Sprite sheet check failed {}", report); });
+
                 let starting_obstacles = stone_and_platform(stone.clone(), sprite_sheet.clone(), 0);
                 let timeline = rightmost(&starting_obstacles);
                 
@@ -1130,54 +2327,85 @@ impl Game for WalkTheDog {
                         walk: Walk {
                 */
 
+                let mut particles = ParticleSystem::new(PARTICLE_CAPACITY);
+                let dust = BoyParticles::register(&mut particles);
+
                 let machine = WalkTheDogStateMachine::new(
                         Walk {
                             boy: rhb,
-                            backgrounds: [
-                                Image::new(background.clone(),
-                                Point { x: 0, y: 0 }),
-                                Image::new(
-                                    background,
-                                    Point {
-                                        x: background_width,
-                                        y: 0,
-                                    },
-                                ),
-                            ],
+                            background: ParallaxBackground::new(layers),
                             obstacles: starting_obstacles,
+                            grid: SpatialGrid::new(OBSTACLE_GRID_COLUMN),
+                            nearby: vec![],
+                            collisions: vec![],
+                            obstacles_moved: true,
                             obstacle_sheet: sprite_sheet,
                             stone,
                             timeline,
+                            camera: Camera::new(WIDTH, HEIGHT),
+                            particles,
+                            dust,
+                            dust_trail: None,
+                            config,
+                            score: 0,
+                            audio,
+                            collect_sound,
                         },
             	);  //});
 
-                Ok(Box::new(WalkTheDog { machine: Some(machine),}))
+                let config_updates = cfg!(debug_assertions)
+                                        .then(|| config::watch(config::GAME_CONFIG, config));
+
+                Ok(Box::new(WalkTheDog { 
+                    machine: Some(machine),
+                    config_updates,
+                    #[cfg(feature = "debug-overlay")]
+                    show_debug: false,
+                }))
             },
             Some(_) => Err(anyhow!("Error: Game is already initialized!")),
         }
     }//^-- async fn initialize
     
-    fn update(&mut self, keystate: &KeyState) {
-        if let Some(machine) = self.machine.take() {
-            self.machine.replace(machine.update(keystate));
+    fn update(&mut self, keystate: &KeyState) -> Result<()> {
+        let machine = self
+                        .machine
+                        .take()
+                        .ok_or_else(|| anyhow!("Error: Game is not initialized!"))?;
+        self.machine.replace(machine.update(keystate));
+
+        if let Some(Ok(Some(config))) = self.config_updates.as_mut().map(|updates| updates.try_next()) {
+            if let Some(machine) = self.machine.as_mut() {
+                machine.walk_mut().set_config(config);
+            }
+        }
+
+        #[cfg(feature = "debug-overlay")]
+        if keystate.was_pressed("F3") {
+            self.show_debug = !self.show_debug;
         }
-        assert!(self.machine.is_some());
+
+        Ok(())
     }//^-- fn update
 
-    fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&Rect::new(Point { x: 0, y: 0 }, 600, 600));
+    fn draw(&self, queue: &mut RenderQueue) -> Result<()> {
+        #[cfg(feature = "debug-overlay")]
+        if let (true, Some(machine)) = (self.show_debug, &self.machine) {
+            machine.walk().draw_debug(queue);
+        }
 
-        if let Some(machine) = &self.machine {
-            machine.draw(renderer);
+        match &self.machine {
+            Some(machine) => machine.draw(queue),
+            None => Ok(()),
         }
     }
 } //^-- impl Game for WalkTheDog
 
-fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
+fn rightmost(obstacle_list: &[Box<dyn Obstacle>]) -> i16 {
     obstacle_list
         .iter()
         .map(|obstacle| obstacle.right())
-        .max_by(|x, y| x.cmp(&y))
+        .max_by(|x, y| x.cmp(y))
         .unwrap_or(0)
 }
 
@@ -1186,8 +2414,7 @@ fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::channel::mpsc::unbounded;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use web_sys::{AudioBuffer, AudioBufferOptions};
 
     use wasm_bindgen_test::wasm_bindgen_test;
@@ -1198,35 +2425,53 @@ mod tests {
     fn test_transition_from_game_over_to_new_game() {
         let (_, receiver) = unbounded();
         let image = HtmlImageElement::new().unwrap();
-        let audio = Audio::new().unwrap();
+        let audio = Audio::new();
         let options = AudioBufferOptions::new(1, 30000.0); //44100
         let sound = Sound {
             buffer: AudioBuffer::new(&options).unwrap(),
         };
         let rhb = RedHatBoy::new(
-            Sheet {
-                frames: HashMap::new(),
-            },
-            image.clone(),
+            Rc::new(SpriteSheet::new(
+                Sheet {
+                    frames: HashMap::new(),
+                },
+                image.clone(),
+            ).unwrap()),
             audio,
-            sound,
+            Some(sound),
+            None,
+            GameConfig::default(),
         );
         let sprite_sheet = SpriteSheet::new(
             Sheet {
                 frames: HashMap::new(),
             },
             image.clone(),
-        );
+        ).unwrap();
+        let mut particles = ParticleSystem::new(0);
+        let dust = BoyParticles::register(&mut particles);
         let walk = Walk {
             boy: rhb,
-            backgrounds: [
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-                Image::new(image.clone(), Point { x: 0, y: 0 }),
-            ],
+            background: ParallaxBackground::new(vec![ParallaxLayer::new(image.clone(), 1.0, 0, true)]),
             obstacles: vec![],
+            grid: SpatialGrid::new(OBSTACLE_GRID_COLUMN),
+            nearby: vec![],
+            collisions: vec![],
+            obstacles_moved: true,
             obstacle_sheet: Rc::new(sprite_sheet),
-            stone: image.clone(),
+            stone: Stone {
+                image: image.clone(),
+                hitboxes: vec![],
+            },
             timeline: 0,
+            camera: Camera::new(WIDTH, HEIGHT),
+            particles,
+            dust,
+            dust_trail: None,
+            config: GameConfig::default(),
+            score: 0,
+            audio: Audio::new(),
+            collect_sound: None,
         };
 
         // ASSERTION
@@ -1243,7 +2488,7 @@ mod tests {
             _state: GameOver {
                 new_game_event: receiver,
             },
-            walk: walk,
+            walk,
         };
 
         // ASSERTION
@@ -1252,5 +2497,579 @@ mod tests {
         assert_eq!(ui.child_element_count(), 0);
 
     }//^-- fn test_transition_from_game_over_to_new_game
+
+    // The state machine needs no browser, and no sheet to play its clips.
+    fn idle(config: GameConfig) -> RedHatBoyStateMachine {
+        idle_on(&CellTable::new(&Sheet { frames: HashMap::new() }).unwrap(), config)
+    }
+
+    fn idle_on(cells: &CellTable, config: GameConfig) -> RedHatBoyStateMachine {
+        let animation = Animation::new(red_hat_boy_states::clips(), cells);
+        RedHatBoyStateMachine::Idle(RedHatBoyState::new(animation, config))
+    }
+
+    // The boy just off the ground.
+    fn jumping(config: GameConfig) -> RedHatBoyStateMachine {
+        idle(config)
+            .transition(Event::Run)
+            .transition(Event::Jump)
+    }
+
+    // The defaults turn both off.
+    fn with_air_moves() -> GameConfig {
+        GameConfig { air_jumps: 1, air_control: 1, ..GameConfig::default() }
+    }
+
+    // Named the way RED_HAT_BOY_TRANSITIONS names them. No `_` arm, so a
+    // new Event doesn't compile until it's named here, next to events().
+    fn event_name(event: Event) -> &'static str {
+        match event {
+            Event::Run => "Run",
+            Event::Slide => "Slide",
+            Event::Update => "Update",
+            Event::Jump => "Jump",
+            Event::AirJump => "AirJump",
+            Event::KnockOut => "KnockOut",
+            Event::Land(_) => "Land",
+            Event::Steer(_) => "Steer",
+        }
+    }
+
+    // At least one of each.
+    fn events() -> [(Event, &'static str); 9] {
+        [
+            Event::Run,
+            Event::Slide,
+            Event::Update,
+            Event::Jump,
+            Event::AirJump,
+            Event::KnockOut,
+            Event::Land(HEIGHT),
+            Event::Steer(1),
+            Event::Steer(-1),
+        ].map(|event| (event, event_name(event)))
+    }
+
+    /*
+    Every state the boy passes through on a jump, a double jump, a slide
+    and a hit, played out until he's back running or knocked out. Again
+    on his second-last life with no invulnerability, then without lives,
+    then air jumps, to spare, so each conditional transition goes both
+    ways somewhere.
+    */
+    fn visited_states() -> Vec<RedHatBoyStateMachine> {
+        const UPDATES: usize = 80;
+        let scripts: [&[Event]; 4] = [
+            &[Event::Run, Event::Jump],
+            &[Event::Run, Event::Jump, Event::AirJump],
+            &[Event::Run, Event::Slide],
+            &[Event::Run, Event::KnockOut],
+        ];
+        let configs = [
+            GameConfig { lives: 3, ..with_air_moves() },
+            GameConfig { lives: 2, invulnerable_ticks: 0, ..GameConfig::default() },
+            with_air_moves(),
+            GameConfig::default(),
+        ];
+
+        let mut visited = vec![];
+        for config in configs {
+            for script in scripts {
+                let mut boy = idle(config);
+                let events = script.iter().copied().chain(std::iter::repeat(Event::Update).take(UPDATES));
+                for event in events {
+                    visited.push(boy.clone());
+                    boy = boy.transition(event);
+                }
+                visited.push(boy);
+            }
+        }
+        visited
+    }
+
+    #[test]
+    fn the_boy_makes_exactly_the_transitions_in_his_table() {
+        let table = &RED_HAT_BOY_TRANSITIONS;
+        let mut states = HashSet::new();
+        let mut made = HashSet::new();
+
+        for boy in visited_states() {
+            let from = boy.name();
+            states.insert(from);
+            for (event, on) in events() {
+                let to = boy.clone().transition(event).name();
+                match table.targets(from, on) {
+                    Some(targets) => assert!(targets.contains(&to), "{} on {} went to {}, which isn't in the table", from, on, to),
+                    None => assert_eq!(to, from, "{} on {} isn't in the table, so it should be ignored", from, on),
+                }
+                made.insert((from, on, to));
+            }
+        }
+
+        assert_eq!(states, table.states().into_iter().collect(), "some state was never reached");
+        for &(from, on, targets) in table.transitions {
+            for &to in targets {
+                assert!(made.contains(&(from, on, to)), "the table has {} on {} to {}, but it never happened", from, on, to);
+            }
+        }
+    }
+
+    /*
+    transition's `_ => self` arm swallows whatever it doesn't list, so
+    every state gets every event, and what his table leaves out has to
+    leave the boy exactly as he was, not only in the same state.
+    */
+    #[test]
+    fn every_state_ignores_the_events_his_table_leaves_out() {
+        let table = &RED_HAT_BOY_TRANSITIONS;
+        let mut tried = HashSet::new();
+
+        for boy in visited_states() {
+            for (event, on) in events() {
+                let next = boy.clone().transition(event);
+                tried.insert((boy.name(), on));
+                if table.targets(boy.name(), on).is_some() {
+                    continue;
+                }
+
+                let (before, after) = (boy.context(), next.context());
+                assert_eq!(next.name(), boy.name(), "{} on {}", boy.name(), on);
+                assert_eq!(after.position, before.position, "{} on {}", boy.name(), on);
+                assert_eq!(after.velocity, before.velocity, "{} on {}", boy.name(), on);
+                assert_eq!((after.lives, after.invulnerable, after.air_jumps),
+                           (before.lives, before.invulnerable, before.air_jumps), "{} on {}", boy.name(), on);
+            }
+        }
+
+        for state in table.states() {
+            for (_event, on) in events() {
+                assert!(tried.contains(&(state, on)), "{} never got {}", state, on);
+            }
+        }
+    }
+
+    #[test]
+    fn the_game_makes_exactly_the_transitions_in_its_table() {
+        let table = &WALK_THE_DOG_TRANSITIONS;
+        let inputs = [
+            (Inputs { arrow_right: true, ..Inputs::default() }, "ArrowRight"),
+            (Inputs { knocked_out: true, ..Inputs::default() }, "KnockedOut"),
+            (Inputs { new_game: true, ..Inputs::default() }, "NewGame"),
+        ];
+        let mut made = HashSet::new();
+
+        let stages = [Stage::Ready, Stage::Walking, Stage::GameOver];
+        for from in stages {
+            assert_eq!(from.next(Inputs::default()), from, "{} moved on nothing", from.name());
+            for (input, on) in inputs {
+                let to = from.next(input).name();
+                match table.targets(from.name(), on) {
+                    Some(targets) => assert!(targets.contains(&to), "{} on {} went to {}, which isn't in the table", from.name(), on, to),
+                    None => assert_eq!(to, from.name(), "{} on {} isn't in the table, so it should be ignored", from.name(), on),
+                }
+                made.insert((from.name(), on, to));
+            }
+        }
+
+        assert_eq!(table.states(), stages.map(Stage::name), "WalkTheDogStateMachine::new starts Ready");
+        for &(from, on, targets) in table.transitions {
+            for &to in targets {
+                assert!(made.contains(&(from, on, to)), "the table has {} on {} to {}, but it never happened", from, on, to);
+            }
+        }
+    }
+
+    #[test]
+    fn jumping_again_in_the_air_spends_the_air_jumps() {
+        let config = GameConfig { air_jumps: 2, ..with_air_moves() };
+        let boy = jumping(config);
+        assert!(matches!(boy, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(boy.context().air_jumps, 2);
+
+        let once = boy.clone().transition(Event::AirJump);
+        assert!(matches!(once, RedHatBoyStateMachine::DoubleJumping(_)));
+        assert_eq!(once.context().velocity.y, config.double_jump_speed);
+        assert_eq!(BoyEffect::between(&boy, &once).collect::<Vec<_>>(), [BoyEffect::DoubleJumped]);
+
+        let twice = once.clone().transition(Event::Update).transition(Event::AirJump);
+        assert!(matches!(twice, RedHatBoyStateMachine::DoubleJumping(_)));
+        assert_eq!(twice.context().air_jumps, 0);
+        assert_eq!(twice.context().velocity.y, config.double_jump_speed);
+
+        let spent = twice.clone().transition(Event::Update).transition(Event::AirJump);
+        assert!(matches!(spent, RedHatBoyStateMachine::DoubleJumping(_)));
+        assert_eq!(spent.context().velocity.y, config.double_jump_speed + config.gravity);
+        assert_eq!(BoyEffect::between(&twice, &spent).count(), 0);
+    }
+
+    #[test]
+    fn no_air_jumps_means_no_double_jump() {
+        let boy = jumping(GameConfig::default());
+
+        let jumped = boy.clone().transition(Event::AirJump);
+
+        assert!(matches!(jumped, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(jumped.context().velocity, boy.context().velocity);
+    }
+
+    #[test]
+    fn landing_from_a_double_jump_runs_and_refills_the_air_jumps() {
+        let config = with_air_moves();
+        let boy = jumping(config).transition(Event::AirJump);
+
+        let landed = boy.clone().transition(Event::Land(HEIGHT));
+        assert!(matches!(landed, RedHatBoyStateMachine::Running(_)));
+        assert_eq!(BoyEffect::between(&boy, &landed).collect::<Vec<_>>(), [BoyEffect::Landed]);
+
+        let jumped = landed.clone().transition(Event::Jump);
+        assert!(matches!(jumped, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(jumped.context().air_jumps, config.air_jumps);
+        assert_eq!(BoyEffect::between(&landed, &jumped).collect::<Vec<_>>(), [BoyEffect::Jumped]);
+
+        let mut falling = jumped.transition(Event::AirJump);
+        while !matches!(falling, RedHatBoyStateMachine::Running(_)) {
+            falling = falling.transition(Event::Update);
+        }
+        assert_eq!(falling.context().position.y, config.floor);
+    }
+
+    #[test]
+    fn a_double_jump_turns_one_somersault() {
+        let jumping = jumping(with_air_moves());
+        assert_eq!(jumping.draw_params().rotation, 0.0);
+
+        let mut boy = jumping.transition(Event::AirJump);
+        let mut turned = 0.0;
+        while turned < std::f32::consts::TAU {
+            let rotation = boy.draw_params().rotation;
+            assert!(turned < rotation, "{} after {}", rotation, turned);
+            turned = rotation;
+            boy = boy.transition(Event::Update);
+        }
+
+        // Back upright, and still in the air, for the rest of the way down.
+        assert!(matches!(boy, RedHatBoyStateMachine::DoubleJumping(_)));
+        assert_eq!(boy.transition(Event::Update).draw_params().rotation, std::f32::consts::TAU);
+    }
+
+    #[test]
+    fn steering_in_the_air_stays_within_the_air_speeds() {
+        let config = with_air_moves();
+        for boy in [jumping(config), jumping(config).transition(Event::AirJump)] {
+            let faster = (0..20).fold(boy.clone(), |boy, _| boy.transition(Event::Steer(1)));
+            assert_eq!(faster.context().velocity.x, config.air_speed_max);
+
+            let slower = (0..20).fold(boy.clone(), |boy, _| boy.transition(Event::Steer(-1)));
+            assert_eq!(slower.context().velocity.x, config.air_speed_min);
+
+            let landed = faster.transition(Event::Land(HEIGHT));
+            assert_eq!(landed.context().velocity.x, config.running_speed);
+        }
+    }
+
+    #[test]
+    fn steering_does_nothing_on_the_ground_or_without_air_control() {
+        let config = GameConfig { lives: 3, ..with_air_moves() };
+        let running = jumping(config).transition(Event::Land(HEIGHT));
+        let sliding = running.clone().transition(Event::Slide);
+        let hurt = running.clone().transition(Event::KnockOut);
+        assert!(matches!(hurt, RedHatBoyStateMachine::Hurt(_)));
+
+        for boy in [running, sliding, hurt] {
+            let steered = boy.clone().transition(Event::Steer(1));
+            assert_eq!(std::mem::discriminant(&steered), std::mem::discriminant(&boy));
+            assert_eq!(steered.context().velocity, boy.context().velocity);
+        }
+
+        let no_control = jumping(GameConfig { air_control: 0, ..config });
+        let steered = no_control.clone().transition(Event::Steer(1));
+        assert_eq!(steered.context().velocity, no_control.context().velocity);
+    }
+
+    #[test]
+    fn without_air_control_a_fast_boy_keeps_his_speed_in_the_air() {
+        let config = GameConfig { running_speed: 9, air_speed_max: 6, ..GameConfig::default() };
+        let boy = jumping(config);
+        assert_eq!(boy.context().velocity.x, config.running_speed);
+
+        for direction in [1, -1] {
+            let steered = boy.clone().transition(Event::Steer(direction));
+            assert_eq!(steered.context().velocity.x, config.running_speed);
+        }
+    }
+
+    #[test]
+    fn only_airborne_jumps_become_double_jumps() {
+        let config = GameConfig { lives: 3, ..with_air_moves() };
+        let running = jumping(config).transition(Event::Land(HEIGHT));
+        let sliding = running.clone().transition(Event::Slide);
+        let hurt = running.clone().transition(Event::KnockOut);
+
+        assert!(matches!(running.transition(Event::AirJump), RedHatBoyStateMachine::Running(_)));
+        for event in [Event::Jump, Event::AirJump] {
+            assert!(matches!(sliding.clone().transition(event), RedHatBoyStateMachine::Sliding(_)));
+            assert!(matches!(hurt.clone().transition(event), RedHatBoyStateMachine::Hurt(_)));
+        }
+
+        // Holding Space in the air is a Jump every tick, and does nothing.
+        let held = jumping(config).transition(Event::Jump);
+        assert!(matches!(held, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(held.context().air_jumps, config.air_jumps);
+
+        let double_jumping = jumping(config).transition(Event::AirJump);
+        assert!(matches!(double_jumping.clone().transition(Event::Slide), RedHatBoyStateMachine::DoubleJumping(_)));
+        assert!(matches!(double_jumping.transition(Event::KnockOut), RedHatBoyStateMachine::Hurt(_)));
+    }
+
+    #[test]
+    fn a_hit_with_lives_to_spare_hurts_and_costs_a_life() {
+        let config = GameConfig { lives: 3, ..GameConfig::default() };
+        let running = idle(config).transition(Event::Run);
+
+        let hurt = running.transition(Event::KnockOut);
+
+        assert!(matches!(hurt, RedHatBoyStateMachine::Hurt(_)));
+        assert_eq!(hurt.context().lives, 2);
+        assert_eq!(hurt.context().invulnerable, config.invulnerable_ticks);
+        assert_eq!(hurt.context().velocity.x, config.running_speed);
+    }
+
+    #[test]
+    fn hits_are_ignored_until_the_invulnerable_ticks_run_out() {
+        let config = GameConfig { lives: 3, ..GameConfig::default() };
+        let mut boy = idle(config).transition(Event::Run).transition(Event::KnockOut);
+        let cells = CellTable::new(&Sheet { frames: HashMap::new() }).unwrap();
+
+        for _ in 0..config.invulnerable_ticks {
+            let contacts = touch(&boy.bounding_box(&cells), boy.context().velocity, 
+                                 boy.context().invulnerable > 0, &mut hazard(boy.bounding_box(&cells)));
+            assert!(!contacts.knocked_out);
+            boy = boy.transition(Event::Update);
+        }
+
+        assert_eq!(boy.context().invulnerable, 0);
+        assert!(matches!(boy, RedHatBoyStateMachine::Running(_)));
+        let contacts = touch(&boy.bounding_box(&cells), boy.context().velocity, false, &mut hazard(boy.bounding_box(&cells)));
+        assert!(contacts.knocked_out);
+
+        let hurt_again = boy.transition(Event::KnockOut);
+        assert!(matches!(hurt_again, RedHatBoyStateMachine::Hurt(_)));
+        assert_eq!(hurt_again.context().lives, 1);
+    }
+
+    #[test]
+    fn a_hit_during_the_hurt_clip_counts_once_invulnerability_is_over() {
+        let config = GameConfig { lives: 3, invulnerable_ticks: 4, ..GameConfig::default() };
+        let mut boy = idle(config).transition(Event::Run).transition(Event::KnockOut);
+
+        let ignored = boy.clone().transition(Event::KnockOut);
+        assert!(matches!(ignored, RedHatBoyStateMachine::Hurt(_)));
+        assert_eq!(ignored.context().lives, 2);
+
+        for _ in 0..config.invulnerable_ticks {
+            boy = boy.transition(Event::Update);
+        }
+        assert!(matches!(boy, RedHatBoyStateMachine::Hurt(_)), "the clip outlasts the invulnerable ticks");
+
+        let hurt_again = boy.transition(Event::KnockOut);
+        assert!(matches!(hurt_again, RedHatBoyStateMachine::Hurt(_)));
+        assert_eq!(hurt_again.context().lives, 1);
+        assert_eq!(hurt_again.context().invulnerable, config.invulnerable_ticks);
+
+        let no_ticks = GameConfig { lives: 2, invulnerable_ticks: 0, ..GameConfig::default() };
+        let hurt = idle(no_ticks).transition(Event::Run).transition(Event::KnockOut);
+        assert!(matches!(hurt.transition(Event::KnockOut), RedHatBoyStateMachine::Falling(_)));
+    }
+
+    #[test]
+    fn the_last_life_falls_and_ends_knocked_out() {
+        let config = GameConfig { lives: 2, ..GameConfig::default() };
+        let hurt = idle(config).transition(Event::Run).transition(Event::KnockOut);
+        let mut boy = (0..config.invulnerable_ticks).fold(hurt, |boy, _| boy.transition(Event::Update));
+
+        boy = boy.transition(Event::KnockOut);
+        assert!(matches!(boy, RedHatBoyStateMachine::Falling(_)));
+        assert_eq!(boy.context().lives, 1);
+        assert_eq!(boy.context().velocity.x, 0);
+
+        for _ in 0..80 {
+            boy = boy.transition(Event::Update);
+        }
+        assert!(matches!(boy, RedHatBoyStateMachine::KnockedOut(_)));
+        assert!(boy.knocked_out());
+    }
+
+    /*
+    Barriers, platforms and the overhead all need a sheet to be built, but
+    to touch_obstacles they're only a kind and some boxes.
+    */
+    struct Block {
+        kind: ObstacleKind,
+        bounding_box: Rect,
+    }
+
+    impl Obstacle for Block {
+        fn kind(&self) -> ObstacleKind {
+            self.kind
+        }
+
+        fn bounding_boxes(&self) -> &[Rect] {
+            std::slice::from_ref(&self.bounding_box)
+        }
+
+        fn draw(&self, _queue: &mut RenderQueue) {}
+
+        fn move_horizontally(&mut self, x: i16) {
+            self.bounding_box.set_x(self.bounding_box.x() + x);
+        }
+        fn right(&self) -> i16 {
+            self.bounding_box.right()
+        }
+    }//^-- impl Obstacle for Block
+
+    // A platform's box following its Path the way Platform does, without the tiles.
+    struct Carrier {
+        start: Rect,
+        path: Path,
+        tick: u16,
+        bounding_box: Rect,
+        velocity: Point,
+    }
+
+    impl Obstacle for Carrier {
+        fn kind(&self) -> ObstacleKind {
+            ObstacleKind::Solid
+        }
+
+        fn bounding_boxes(&self) -> &[Rect] {
+            std::slice::from_ref(&self.bounding_box)
+        }
+
+        fn update(&mut self) {
+            self.tick = (self.tick + 1) % self.path.period();
+            let offset = self.path.offset(self.tick);
+            let moved = Rect::new_from_x_y(self.start.x() + offset.x, self.start.y() + offset.y,
+                                           self.start.width, self.start.height);
+            self.velocity = Point { x: moved.x() - self.bounding_box.x(), y: moved.y() - self.bounding_box.y() };
+            self.bounding_box = moved;
+        }
+
+        fn velocity(&self) -> Point {
+            self.velocity
+        }
+
+        fn draw(&self, _queue: &mut RenderQueue) {}
+
+        fn move_horizontally(&mut self, x: i16) {
+            self.start.set_x(self.start.x() + x);
+            self.bounding_box.set_x(self.bounding_box.x() + x);
+        }
+        fn right(&self) -> i16 {
+            self.bounding_box.right()
+        }
+    }//^-- impl Obstacle for Carrier
+
+    fn hazard(bounding_box: Rect) -> Vec<Box<dyn Obstacle>> {
+        vec![Box::new(Block { kind: ObstacleKind::Hazard, bounding_box })]
+    }
+
+    // The boy's box against `obstacles`, the way Walk checks it each tick.
+    fn touch(boy: &Rect, velocity: Point, invulnerable: bool, obstacles: &mut Vec<Box<dyn Obstacle>>) -> Contacts {
+        let mut grid = SpatialGrid::new(OBSTACLE_GRID_COLUMN);
+        grid.rebuild(&obstacles.iter().map(|obstacle| obstacle.bounds()).collect::<Vec<_>>());
+        touch_obstacles(boy, velocity, invulnerable, obstacles, &grid, &mut vec![], &mut vec![])
+    }
+
+    #[test]
+    fn touching_a_coin_scores_it_and_removes_it() {
+        let mut obstacles: Vec<Box<dyn Obstacle>> = vec![
+            Box::new(Collectible::new(Point { x: 100, y: 450 }, 10)),
+            Box::new(Collectible::new(Point { x: 400, y: 450 }, 5)),
+        ];
+        let boy = Rect::new_from_x_y(90, 420, 60, 100);
+
+        let contacts = touch(&boy, Point { x: 3, y: 0 }, false, &mut obstacles);
+
+        assert_eq!(contacts.collected, vec![(10, Point { x: 112, y: 462 })]);
+        assert!(!contacts.knocked_out);
+        assert_eq!(contacts.landing, None);
+        assert!(contacts.removed);
+        assert_eq!(obstacles.len(), 1);
+        assert_eq!(obstacles[0].kind(), ObstacleKind::Collectible { value: 5 });
+    }
+
+    #[test]
+    fn a_coin_next_to_a_hazard_still_counts() {
+        let mut obstacles: Vec<Box<dyn Obstacle>> = vec![
+            Box::new(Block { kind: ObstacleKind::Hazard, bounding_box: Rect::new_from_x_y(140, 470, 40, 40) }),
+            Box::new(Collectible::new(Point { x: 100, y: 450 }, 10)),
+        ];
+        let boy = Rect::new_from_x_y(90, 420, 60, 100);
+
+        let contacts = touch(&boy, Point { x: 3, y: 0 }, false, &mut obstacles);
+
+        assert_eq!(contacts.collected, vec![(10, Point { x: 112, y: 462 })]);
+        assert!(contacts.knocked_out);
+        assert_eq!(obstacles.len(), 1);
+        assert_eq!(obstacles[0].kind(), ObstacleKind::Hazard);
+    }
+
+    // The shipped sheet's, so his boxes are the real frames' hitboxes.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rhb_cells() -> CellTable {
+        let pix = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("www/resources/pix");
+        CellTable::new(&crate::validation::load_sheet(&pix.join("rhb.json")).unwrap()).unwrap()
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn only_a_sliding_boy_gets_under_an_overhead() {
+        let cells = rhb_cells();
+        let running = idle_on(&cells, GameConfig::default()).transition(Event::Run);
+        let sliding = running.clone().transition(Event::Slide);
+        let jumping = running.clone().transition(Event::Jump).transition(Event::Update);
+        let overhead = crate::segments::overhead_bounding_box(running.bounding_box(&cells).x());
+
+        for (boy, knocked_out) in [(sliding, false), (running, true), (jumping, true)] {
+            let mut obstacles = hazard(overhead);
+            let bounding_box = boy.bounding_box(&cells);
+            assert!(bounding_box.x() < overhead.right() && overhead.x() < bounding_box.right(), "{}", boy.name());
+
+            let contacts = touch(&bounding_box, boy.context().velocity, false, &mut obstacles);
+            assert_eq!(contacts.knocked_out, knocked_out, "{}", boy.name());
+        }
+    }
+
+    // A tick the way Walking's update does it: obstacles, the boy, then collisions.
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn moving_platforms_carry_the_boy() {
+        const TOP: i16 = 420;
+        let cells = rhb_cells();
+        let config = GameConfig::default();
+
+        for path in [Path::Patrol { distance: 160, period: 240 }, Path::Bob { amplitude: 40, period: 150 }] {
+            let mut boy = idle_on(&cells, config).transition(Event::Run).transition(Event::Land(TOP));
+            let start = Rect::new_from_x_y(boy.bounding_box(&cells).x() - 40, TOP, 384, 93);
+            let mut obstacles: Vec<Box<dyn Obstacle>> = vec![
+                Box::new(Carrier { start, path, tick: 0, bounding_box: start, velocity: Point::default() }),
+            ];
+
+            // Long enough to see it speed up and slow down, short of running off the end.
+            for tick in 0..60 {
+                obstacles.iter_mut().for_each(|obstacle| obstacle.update());
+                let before = boy.context().position.x;
+                boy = boy.transition(Event::Update);
+                let contacts = touch(&boy.bounding_box(&cells), boy.context().velocity, false, &mut obstacles);
+                boy = boy.touch(&contacts);
+
+                let platform = obstacles[0].bounding_boxes()[0];
+                assert_eq!(contacts.landing, Some((platform.y(), obstacles[0].velocity())), "{:?} at {}", path, tick);
+                assert!(matches!(boy, RedHatBoyStateMachine::Running(_)), "{:?} at {}", path, tick);
+                assert_eq!(boy.context().position.x - before, config.running_speed + obstacles[0].velocity().x,
+                           "{:?} at {}", path, tick);
+            }
+        }
+    }
 }//-- mod tests
 
@@ -1,11 +1,19 @@
 #[macro_use]
 mod browser;
-mod engine;
+mod config;
+pub mod engine;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod atlas;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod diagrams;
 mod game;
 mod segments;
 mod sound;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod validation;
 
 use engine::GameLoop;
+use futures::StreamExt;
 use game::WalkTheDog;
 use wasm_bindgen::prelude::*;
 
@@ -14,15 +22,36 @@ use wasm_bindgen::prelude::*;
 pub fn main_js() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
 
-    browser::spawn_local(async move {
+    browser::spawn_local(start_game());
+
+    Ok(())
+}
+
+// Keeps offering a retry until the game starts, instead of
+// leaving the player with a blank canvas.
+async fn start_game() {
+    loop {
         let game = WalkTheDog::new();
 
-        GameLoop::start(game)
-            .await
-            .expect("Could not start game loop");
-    });
+        let err = match GameLoop::start(game).await {
+            Ok(()) => break,
+            Err(err) => err,
+        };
+        error!("Could not start game loop {:#?}", err);
 
-    Ok(())
+        let mut retry = match engine::draw_error_screen(&err) {
+            Ok(retry) => retry,
+            Err(err) => {
+                error!("Could not show the error screen {:#?}", err);
+                break;
+            }
+        };
+        retry.next().await;
+
+        if let Err(err) = browser::hide_ui() {
+            error!("Error hiding the error screen {:#?}", err);
+        }
+    }
 }
 
 /*
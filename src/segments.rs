@@ -1,8 +1,7 @@
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
 
-use crate::game::{Barrier, Obstacle, Platform};
-use crate::engine::{Image, Point, Rect, SpriteSheet};
+use crate::game::{Barrier, Collectible, Obstacle, Overhead, Platform, Stone};
+use crate::engine::{Image, Path, Point, Rect, SpriteSheet};
 
 const LOW_PLATFORM: i16 = 420;
 const HIGH_PLATFORM: i16 = 375;
@@ -10,7 +9,27 @@ const FIRST_PLATFORM: i16 = 370;
 
 const STONE_ON_GROUND: i16 = 555; //546;
 
-const FLOATING_PLATFORM_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
+// Coins sit where a jump over the stone or a run along the platform passes.
+const COIN_OVER_STONE: i16 = 450;
+const COIN_OVER_PLATFORM: i16 = 60;
+const COIN_VALUE: u32 = 1;
+
+// Slow enough that a landing is still a matter of timing, not luck.
+const BOB: Path = Path::Bob { amplitude: 40, period: 150 };
+const PATROL: Path = Path::Patrol { distance: 160, period: 240 };
+
+/*
+The boy's box tops out at 501 running and 551 sliding, so an overhead
+ending at 530 stops one and not the other. It reaches higher than he
+can jump.
+*/
+pub const OVERHEAD_SPRITE: &str = "5.png";
+const OVERHEAD_WIDTH: i16 = 128;
+const OVERHEAD_BOTTOM: i16 = 530;
+const OVERHEAD_HEIGHT: i16 = 512;
+const COIN_UNDER_OVERHEAD: i16 = 565;
+
+pub const FLOATING_PLATFORM_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
 const PLATFORM_WIDTH: i16 = 384;
 const PLATFORM_HEIGHT: i16 = 93;
 const PLATFORM_EDGE_WIDTH: i16 = 60;
@@ -33,31 +52,46 @@ const FLOATING_PLATFORM_BOUNDING_BOXES: [Rect; 3] = [
 
 
 pub fn stone_and_platform( 
-            stone: HtmlImageElement, 
+            stone: Stone, 
             sprite_sheet: Rc<SpriteSheet>, 
             offset_x: i16, ) -> Vec<Box<dyn Obstacle>> {
 
         const INITIAL_STONE_OFFSET: i16 = 150;
         vec![
-            Box::new(Barrier::new(Image::new(
-                stone,
-                Point {
-                    x: offset_x + INITIAL_STONE_OFFSET,
-                    y: STONE_ON_GROUND,
-                },
-            ))),
+            Box::new(Barrier::new(
+                Image::new(
+                    stone.image,
+                    Point {
+                        x: offset_x + INITIAL_STONE_OFFSET,
+                        y: STONE_ON_GROUND,
+                    },
+                ),
+                &stone.hitboxes,
+            )),
             Box::new(create_floating_platform(
                 sprite_sheet,
                 Point {
                     x: offset_x + FIRST_PLATFORM,
                     y: LOW_PLATFORM,
             },
+                Path::Still,
+            )),
+            Box::new(Collectible::new(
+                Point { x: offset_x + INITIAL_STONE_OFFSET, y: COIN_OVER_STONE },
+                COIN_VALUE,
+            )),
+            Box::new(Collectible::new(
+                Point {
+                    x: offset_x + FIRST_PLATFORM + PLATFORM_WIDTH / 2,
+                    y: LOW_PLATFORM - COIN_OVER_PLATFORM,
+                },
+                COIN_VALUE,
             )),
         ]
 }
 
 pub fn platform_and_stone(
-    stone: HtmlImageElement,
+    stone: Stone,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
 ) -> Vec<Box<dyn Obstacle>> {
@@ -65,30 +99,129 @@ pub fn platform_and_stone(
     const INITIAL_PLATFORM_OFFSET: i16 = 200;
 
     vec![
-        Box::new(Barrier::new(Image::new(
-            stone,
+        Box::new(Barrier::new(
+            Image::new(
+                stone.image,
+                Point {
+                    x: offset_x + INITIAL_STONE_OFFSET,
+                    y: STONE_ON_GROUND,
+                },
+            ),
+            &stone.hitboxes,
+        )),
+        Box::new(create_floating_platform(
+            sprite_sheet,
+            Point {
+                x: offset_x + INITIAL_PLATFORM_OFFSET,
+                y: HIGH_PLATFORM,
+            },
+            Path::Still,
+        )),
+        Box::new(Collectible::new(
             Point {
-                x: offset_x + INITIAL_STONE_OFFSET,
-                y: STONE_ON_GROUND,
+                x: offset_x + INITIAL_PLATFORM_OFFSET + PLATFORM_WIDTH / 2,
+                y: HIGH_PLATFORM - COIN_OVER_PLATFORM,
             },
-        ))),
+            COIN_VALUE,
+        )),
+        Box::new(Collectible::new(
+            Point { x: offset_x + INITIAL_STONE_OFFSET, y: COIN_OVER_STONE },
+            COIN_VALUE,
+        )),
+    ]
+}
+
+// A bobbing platform over the stone, then one patrolling further on.
+pub fn moving_platforms(
+    stone: Stone,
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+) -> Vec<Box<dyn Obstacle>> {
+    const INITIAL_STONE_OFFSET: i16 = 150;
+    const PATROL_OFFSET: i16 = FIRST_PLATFORM + PLATFORM_WIDTH + 80;
+
+    vec![
+        Box::new(Barrier::new(
+            Image::new(
+                stone.image,
+                Point {
+                    x: offset_x + INITIAL_STONE_OFFSET,
+                    y: STONE_ON_GROUND,
+                },
+            ),
+            &stone.hitboxes,
+        )),
+        Box::new(create_floating_platform(
+            sprite_sheet.clone(),
+            Point {
+                x: offset_x + FIRST_PLATFORM,
+                y: LOW_PLATFORM,
+            },
+            BOB,
+        )),
         Box::new(create_floating_platform(
             sprite_sheet,
             Point {
-                x: offset_x + INITIAL_PLATFORM_OFFSET,
+                x: offset_x + PATROL_OFFSET,
                 y: HIGH_PLATFORM,
             },
+            PATROL,
+        )),
+        Box::new(Collectible::new(
+            Point { x: offset_x + INITIAL_STONE_OFFSET, y: COIN_OVER_STONE },
+            COIN_VALUE,
+        )),
+    ]
+}
+
+// Where an overhead starting at `x` collides.
+pub fn overhead_bounding_box(x: i16) -> Rect {
+    Rect::new_from_x_y(x, OVERHEAD_BOTTOM - OVERHEAD_HEIGHT, OVERHEAD_WIDTH, OVERHEAD_HEIGHT)
+}
+
+// An overhead to slide under, with a coin beneath it, then a stone to jump.
+pub fn slide_under(
+    stone: Stone,
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+) -> Vec<Box<dyn Obstacle>> {
+    const INITIAL_OVERHEAD_OFFSET: i16 = 200;
+    const INITIAL_STONE_OFFSET: i16 = 650;
+
+    vec![
+        Box::new(Overhead::new(
+            sprite_sheet,
+            OVERHEAD_SPRITE,
+            overhead_bounding_box(offset_x + INITIAL_OVERHEAD_OFFSET),
+        )),
+        Box::new(Collectible::new(
+            Point {
+                x: offset_x + INITIAL_OVERHEAD_OFFSET + OVERHEAD_WIDTH / 2,
+                y: COIN_UNDER_OVERHEAD,
+            },
+            COIN_VALUE,
+        )),
+        Box::new(Barrier::new(
+            Image::new(
+                stone.image,
+                Point {
+                    x: offset_x + INITIAL_STONE_OFFSET,
+                    y: STONE_ON_GROUND,
+                },
+            ),
+            &stone.hitboxes,
         )),
     ]
 }
 
 pub fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, 
-                            position: Point) -> Platform {
+                            position: Point, path: Path) -> Platform {
     Platform::new(
         sprite_sheet,
         position,
         &FLOATING_PLATFORM_SPRITES,
         &FLOATING_PLATFORM_BOUNDING_BOXES,
+        path,
     )
 }
 
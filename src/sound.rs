@@ -6,6 +6,7 @@ use js_sys::ArrayBuffer;
 use wasm_bindgen_futures::JsFuture;
 use wasm_bindgen::JsCast;  
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum LOOPING {
     NO,
     YES,
@@ -25,7 +26,7 @@ fn connect_with_audio_node( buffer_source: &AudioBufferSourceNode,
                             destination: &AudioDestinationNode,
                           ) -> Result<AudioNode> {
 
-    buffer_source.connect_with_audio_node(&destination)
+    buffer_source.connect_with_audio_node(destination)
                  .map_err(|err| anyhow!("Error connecting audio source to destination {:#?}", err))
 }
 
@@ -33,7 +34,7 @@ fn connect_with_audio_node( buffer_source: &AudioBufferSourceNode,
 /*
 pub fn play_sound(ctx: &AudioContext, buffer: &AudioBuffer) -> Result<()> {
     let track_source = create_buffer_source(ctx)?;
-    track_source.set_buffer(Some(&buffer));
+    track_source.set_buffer(Some(buffer));
     connect_with_audio_node(&track_source, &ctx.destination())?;
 
     track_source
@@ -55,11 +56,23 @@ pub fn play_sound( ctx: &AudioContext,
         .map_err(|err| anyhow!("Could not start sound!{:#?}", err))
 }
 
+// `rate` times as fast, which pitches it up as much.
+pub fn play_sound_at_rate( ctx: &AudioContext,
+                           buffer: &AudioBuffer,
+                           rate: f32) -> Result<()> {
+
+    let track_source = create_track_source(ctx, buffer)?;
+    track_source.playback_rate().set_value(rate);
+
+    track_source
+        .start()
+        .map_err(|err| anyhow!("Could not start sound!{:#?}", err))
+}
 
 fn create_track_source( ctx: &AudioContext, 
                         buffer: &AudioBuffer) -> Result<AudioBufferSourceNode> {
     let track_source = create_buffer_source(ctx)?;
-    track_source.set_buffer(Some(&buffer));
+    track_source.set_buffer(Some(buffer));
     connect_with_audio_node(&track_source, &ctx.destination())?;
 
     Ok(track_source)
@@ -68,7 +81,7 @@ fn create_track_source( ctx: &AudioContext,
 pub async fn decode_audio_data( ctx: &AudioContext, 
                                 array_buffer: &ArrayBuffer,) -> Result<AudioBuffer> {
 
-    JsFuture::from( ctx.decode_audio_data(&array_buffer)
+    JsFuture::from( ctx.decode_audio_data(array_buffer)
                        .map_err(|err| anyhow!("Could not decode audio from array buffer {:#?}", err))?,
                   ).await
                     .map_err(|err| anyhow!("Could not convert promise to future {:#?}", err))?
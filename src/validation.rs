@@ -0,0 +1,39 @@
+/*
+Sprite sheet validation for the native asset tools.
+
+The same check_sheets pass the game runs at load time, run against the
+JSON files on disk, so a missing frame is caught before anyone opens
+a browser.
+*/
+use anyhow::{anyhow, Result};
+use std::{fs, path::Path};
+
+use crate::{engine::{Sheet, SheetReport}, game};
+
+pub fn load_sheet(path: &Path) -> Result<Sheet> {
+    let json = fs::read_to_string(path)
+                .map_err(|err| anyhow!("Could not read {}: {}", path.display(), err))?;
+    serde_json::from_str(&json)
+        .map_err(|err| anyhow!("Could not parse {}: {}", path.display(), err))
+}
+
+pub fn validate_sheets(rhb_json: &Path, tiles_json: &Path) -> Result<Vec<SheetReport>> {
+    Ok(game::check_sheets(&load_sheet(rhb_json)?, &load_sheet(tiles_json)?))
+}
+
+//======================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_sheets_have_every_cell_the_game_uses() {
+        let pix = Path::new(env!("CARGO_MANIFEST_DIR")).join("www/resources/pix");
+        let reports = validate_sheets(&pix.join("rhb.json"), &pix.join("tiles.json")).unwrap();
+
+        for report in reports {
+            assert!(report.is_ok(), "{}", report);
+        }
+    }
+}//^-- mod tests